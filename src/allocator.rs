@@ -0,0 +1,380 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::c_void;
+use std::mem;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::Mutex;
+
+use ocl_core::types::abs::{Context, Event, Mem};
+use ocl_core::{MemFlags, Status};
+
+use crate::OpenCLError;
+
+const MIN_CLASS_SIZE: usize = 64;
+
+thread_local! {
+    // A buffer created outside of the pool, handed out by the next `new_handle` call.
+    static IMPORTED_BUFFER: RefCell<Option<Mem>> = RefCell::new(None);
+    // A handle allocated beforehand by a fallible call, handed out by the next `new_handle`.
+    static ALLOCATED_HANDLE: RefCell<Option<AtomicPtr<c_void>>> = RefCell::new(None);
+}
+
+pub fn with_imported_buffer<R, F: FnOnce() -> R>(buffer: Mem, f: F) -> R {
+    IMPORTED_BUFFER.with(|imported| *imported.borrow_mut() = Some(buffer));
+    let ret = f();
+    IMPORTED_BUFFER.with(|imported| imported.borrow_mut().take());
+    ret
+}
+
+pub fn with_allocated_handle<R, F: FnOnce() -> R>(handle: AtomicPtr<c_void>, f: F) -> R {
+    ALLOCATED_HANDLE.with(|allocated| *allocated.borrow_mut() = Some(handle));
+    let ret = f();
+    ALLOCATED_HANDLE.with(|allocated| allocated.borrow_mut().take());
+    ret
+}
+
+pub fn take_allocated_handle() -> Option<AtomicPtr<c_void>> {
+    ALLOCATED_HANDLE.with(|allocated| allocated.borrow_mut().take())
+}
+
+// Rounds up to a quarter of the largest power of two not exceeding `size`,
+// which bounds the wasted space to 25% per buffer.
+pub fn size_class(size: usize) -> usize {
+    if size <= MIN_CLASS_SIZE {
+        return MIN_CLASS_SIZE;
+    }
+    let step = (1 << (mem::size_of::<usize>() * 8 - 1 - size.leading_zeros() as usize)) / 4;
+    (size + step - 1) / step * step
+}
+
+fn class_bytes(class: usize) -> usize {
+    class * mem::size_of::<f32>()
+}
+
+fn is_allocation_failure(e: &ocl_core::Error) -> bool {
+    e.api_status() == Some(Status::CL_MEM_OBJECT_ALLOCATION_FAILURE)
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStats {
+    pub allocated_bytes: usize,
+    pub peak_allocated_bytes: usize,
+    pub cached_bytes: usize,
+    pub num_allocations: usize,
+    pub num_driver_allocations: usize,
+    pub num_frees: usize,
+    pub live_handles: usize,
+    pub global_mem_size: u64,
+}
+
+#[derive(Default)]
+struct PoolState {
+    // Each cached buffer keeps the events that must complete before it is reused.
+    free: BTreeMap<usize, Vec<(Mem, Vec<Event>)>>,
+    live: HashMap<usize, usize>,
+    imported: HashSet<usize>,
+    cached_bytes: usize,
+    limit: Option<usize>,
+    allocated_bytes: usize,
+    peak_allocated_bytes: usize,
+    num_allocations: usize,
+    num_driver_allocations: usize,
+    num_frees: usize,
+}
+
+// Called with each buffer the pool gives back to the driver.
+pub type ReleaseHook = dyn Fn(&Mem) + Send + Sync;
+
+pub struct MemoryPool {
+    context: Context,
+    flags: MemFlags,
+    cap: usize,
+    state: Mutex<PoolState>,
+    on_release: Option<Box<ReleaseHook>>,
+}
+
+impl MemoryPool {
+    pub fn new(
+        context: &Context,
+        flags: MemFlags,
+        cap: usize,
+        limit: Option<usize>,
+        on_release: Option<Box<ReleaseHook>>,
+    ) -> MemoryPool {
+        MemoryPool {
+            context: context.clone(),
+            flags: flags,
+            cap: cap,
+            state: Mutex::new(PoolState {
+                limit: limit,
+                ..PoolState::default()
+            }),
+            on_release: on_release,
+        }
+    }
+
+    fn release(&self, buffer: Mem) {
+        if let Some(on_release) = &self.on_release {
+            on_release(&buffer);
+        }
+    }
+
+    fn purge(&self, state: &mut PoolState) {
+        for (_, buffers) in mem::replace(&mut state.free, BTreeMap::new()) {
+            for (buffer, _) in buffers {
+                self.release(buffer);
+            }
+        }
+        state.cached_bytes = 0;
+    }
+
+    fn create_buffer(&self, class: usize) -> Result<Mem, ocl_core::Error> {
+        unsafe { ocl_core::create_buffer(&self.context, self.flags, class, None::<&[f32]>) }
+    }
+
+    pub fn new_handle(&self, size: usize) -> Result<(AtomicPtr<c_void>, Vec<Event>), OpenCLError> {
+        let class = size_class(size);
+        let mut state = self.state.lock().unwrap();
+        if let Some(buffer) = IMPORTED_BUFFER.with(|imported| imported.borrow_mut().take()) {
+            let handle = Box::into_raw(Box::new(buffer));
+            state.imported.insert(handle as usize);
+            return Ok((AtomicPtr::new(handle as *mut c_void), vec![]));
+        }
+        let cached = state.free.get_mut(&class).and_then(|buffers| buffers.pop());
+        let (buffer, release_events) = match cached {
+            Some(cached) => {
+                state.cached_bytes -= class_bytes(class);
+                cached
+            }
+            None => {
+                let out_of_memory = |state: &PoolState| OpenCLError::OutOfMemory {
+                    requested: size * mem::size_of::<f32>(),
+                    allocated: state.allocated_bytes,
+                    limit: state.limit,
+                };
+                if let Some(limit) = state.limit {
+                    // Cached buffers still occupy the device, so they count against the limit.
+                    if state.allocated_bytes + state.cached_bytes + class_bytes(class) > limit {
+                        self.purge(&mut state);
+                        if state.allocated_bytes + class_bytes(class) > limit {
+                            return Err(out_of_memory(&state));
+                        }
+                    }
+                }
+                let buffer = match self.create_buffer(class) {
+                    Err(ref e) if is_allocation_failure(e) => {
+                        self.purge(&mut state);
+                        match self.create_buffer(class) {
+                            Err(ref e) if is_allocation_failure(e) => {
+                                return Err(out_of_memory(&state));
+                            }
+                            result => result.map_err(OpenCLError::Allocation)?,
+                        }
+                    }
+                    result => result.map_err(OpenCLError::Allocation)?,
+                };
+                state.num_driver_allocations += 1;
+                (buffer, vec![])
+            }
+        };
+        let handle = Box::into_raw(Box::new(buffer));
+        state.live.insert(handle as usize, class);
+        state.num_allocations += 1;
+        state.allocated_bytes += class_bytes(class);
+        if state.allocated_bytes > state.peak_allocated_bytes {
+            state.peak_allocated_bytes = state.allocated_bytes;
+        }
+        Ok((AtomicPtr::new(handle as *mut c_void), release_events))
+    }
+
+    pub fn drop_handle(&self, handle: &AtomicPtr<c_void>, release_events: Vec<Event>) {
+        let handle = handle.load(Ordering::Acquire) as *mut Mem;
+        let buffer = unsafe { *Box::from_raw(handle) };
+        let mut state = self.state.lock().unwrap();
+        // Imported buffers are owned by their creator and never cached.
+        if state.imported.remove(&(handle as usize)) {
+            self.release(buffer);
+            return;
+        }
+        let class = state.live.remove(&(handle as usize)).unwrap();
+        state.num_frees += 1;
+        state.allocated_bytes -= class_bytes(class);
+        if state.cached_bytes + class_bytes(class) <= self.cap {
+            state.cached_bytes += class_bytes(class);
+            state
+                .free
+                .entry(class)
+                .or_insert_with(Vec::new)
+                .push((buffer, release_events));
+        } else {
+            self.release(buffer);
+        }
+    }
+
+    pub fn release_cached(&self) {
+        let mut state = self.state.lock().unwrap();
+        self.purge(&mut state);
+    }
+
+    pub fn cached_bytes(&self) -> usize {
+        self.state.lock().unwrap().cached_bytes
+    }
+
+    pub fn stats(&self, global_mem_size: u64) -> MemoryStats {
+        let state = self.state.lock().unwrap();
+        MemoryStats {
+            allocated_bytes: state.allocated_bytes,
+            peak_allocated_bytes: state.peak_allocated_bytes,
+            cached_bytes: state.cached_bytes,
+            num_allocations: state.num_allocations,
+            num_driver_allocations: state.num_driver_allocations,
+            num_frees: state.num_frees,
+            live_handles: state.live.len(),
+            global_mem_size: global_mem_size,
+        }
+    }
+
+    pub fn set_limit(&self, limit: Option<usize>) {
+        self.state.lock().unwrap().limit = limit;
+    }
+
+    pub fn reset_peak(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.peak_allocated_bytes = state.allocated_bytes;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::size_class;
+    use crate::{DeviceSelector, OpenCL, OpenCLBuilder, OpenCLError};
+    use prima_undine::functions::BasicFunctions;
+    use prima_undine::shape;
+
+    #[test]
+    fn check_size_class() {
+        assert_eq!(64, size_class(0));
+        assert_eq!(64, size_class(1));
+        assert_eq!(64, size_class(64));
+        assert_eq!(80, size_class(65));
+        assert_eq!(112, size_class(100));
+        assert_eq!(128, size_class(128));
+        assert_eq!(1280, size_class(1025));
+        assert_eq!(1 << 20, size_class(1 << 20));
+        assert_eq!(5 << 18, size_class((1 << 20) + 1));
+    }
+
+    #[test]
+    fn check_memory_pool() {
+        let dev = OpenCL::from_env().unwrap();
+        let cl = OpenCL::from_device(&dev).unwrap();
+        {
+            let x = dev.new_tensor_by_slice(shape![2, 2], &[1., 2., 3., 4.]);
+            assert_vector_ulps_eq!(vec![1., 2., 3., 4.], x.to_vec());
+        }
+        assert_eq!(256, cl.cached_memory_bytes());
+        {
+            let y = dev.new_tensor_by_slice(shape![3], &[5., 6., 7.]);
+            assert_eq!(0, cl.cached_memory_bytes());
+            assert_vector_ulps_eq!(vec![5., 6., 7.], y.to_vec());
+            let _z = dev.new_tensor_by_slice(shape![100], &[0.; 100]);
+        }
+        assert_eq!(256 + 448, cl.cached_memory_bytes());
+        cl.release_cached_memory();
+        assert_eq!(0, cl.cached_memory_bytes());
+    }
+
+    #[test]
+    fn check_memory_pool_cap() {
+        let dev = OpenCLBuilder::new()
+            .selector(DeviceSelector::from_env().unwrap())
+            .memory_pool_cap(256)
+            .build()
+            .unwrap();
+        let cl = OpenCL::from_device(&dev).unwrap();
+        {
+            let _x = dev.new_tensor_by_slice(shape![2, 2], &[1., 2., 3., 4.]);
+            let _y = dev.new_tensor_by_slice(shape![100], &[0.; 100]);
+        }
+        assert_eq!(256, cl.cached_memory_bytes());
+    }
+
+    #[test]
+    fn check_memory_stats() {
+        let dev = OpenCL::from_env().unwrap();
+        let cl = OpenCL::from_device(&dev).unwrap();
+        let stats = cl.memory_stats();
+        assert_eq!(0, stats.allocated_bytes);
+        assert_eq!(0, stats.live_handles);
+        assert_eq!(cl.properties().global_mem_size, stats.global_mem_size);
+        assert!(stats.global_mem_size > 0);
+
+        let x = dev.new_tensor_by_slice(shape![2, 2], &[1., 2., 3., 4.]);
+        {
+            let _y = dev.new_tensor_by_slice(shape![100], &[0.; 100]);
+            let stats = cl.memory_stats();
+            assert_eq!(256 + 448, stats.allocated_bytes);
+            assert_eq!(256 + 448, stats.peak_allocated_bytes);
+            assert_eq!(2, stats.num_allocations);
+            assert_eq!(2, stats.num_driver_allocations);
+            assert_eq!(2, stats.live_handles);
+        }
+        let stats = cl.memory_stats();
+        assert_eq!(256, stats.allocated_bytes);
+        assert_eq!(256 + 448, stats.peak_allocated_bytes);
+        assert_eq!(448, stats.cached_bytes);
+        assert_eq!(1, stats.num_frees);
+        assert_eq!(1, stats.live_handles);
+
+        cl.reset_peak_memory();
+        assert_eq!(256, cl.memory_stats().peak_allocated_bytes);
+        {
+            let _y = dev.new_tensor_by_slice(shape![100], &[0.; 100]);
+            let stats = cl.memory_stats();
+            assert_eq!(3, stats.num_allocations);
+            assert_eq!(2, stats.num_driver_allocations);
+        }
+        drop(x);
+        assert_eq!(0, cl.memory_stats().live_handles);
+    }
+
+    #[test]
+    fn check_memory_limit() {
+        let dev = OpenCLBuilder::new()
+            .selector(DeviceSelector::from_env().unwrap())
+            .memory_limit(1024)
+            .build()
+            .unwrap();
+        let cl = OpenCL::from_device(&dev).unwrap();
+        let x = dev.new_tensor_by_slice(shape![2, 2], &[1., 2., 3., 4.]);
+        {
+            let _y = dev.new_tensor_by_slice(shape![100], &[0.; 100]);
+        }
+        // The cached buffer is purged to make room.
+        let _z = dev.new_tensor_by_slice(shape![160], &[0.; 160]);
+        assert_eq!(256 + 640, cl.memory_stats().allocated_bytes);
+
+        match cl.try_new_tensor(&dev, shape![100]) {
+            Err(OpenCLError::OutOfMemory {
+                requested,
+                allocated,
+                limit,
+            }) => {
+                assert_eq!(400, requested);
+                assert_eq!(256 + 640, allocated);
+                assert_eq!(Some(1024), limit);
+            }
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("allocation must fail"),
+        }
+
+        // The device stays usable after the error.
+        assert_eq!(256 + 640, cl.memory_stats().allocated_bytes);
+        assert_vector_ulps_eq!(vec![1., 2., 3., 4.], x.to_vec());
+        cl.set_memory_limit(None);
+        let mut y = cl.try_new_tensor(&dev, shape![100]).unwrap();
+        dev.call_fw_impl("reset_tensor_impl", &[], &[], &[1.], &mut [&mut y]);
+        assert_vector_ulps_eq!(vec![1.; 100], y.to_vec());
+        assert_eq!(256 + 640 + 448, cl.memory_stats().allocated_bytes);
+    }
+}
@@ -0,0 +1,318 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use ocl_core::types::abs::{CommandQueue, Context};
+use ocl_core::CommandQueueProperties;
+
+use prima_undine::Device;
+
+use crate::{properties, DeviceSelector, OpenCL, OpenCLError, OpenCLInternal};
+
+#[derive(Clone, Debug)]
+pub struct OpenCLBuilder {
+    pub(crate) selector: DeviceSelector,
+    pub(crate) build_options: Vec<String>,
+    pub(crate) queue_properties: Option<CommandQueueProperties>,
+    pub(crate) warm_up: bool,
+    pub(crate) program_cache: Option<PathBuf>,
+    pub(crate) profiling: bool,
+    pub(crate) tracing: bool,
+    pub(crate) memory_pool_cap: usize,
+    pub(crate) memory_limit: Option<usize>,
+    pub(crate) zero_copy: bool,
+    pub(crate) streams: usize,
+    pub(crate) out_of_order: bool,
+    pub(crate) native_gemm: bool,
+    #[cfg(feature = "clblast-dynamic")]
+    pub(crate) clblast_library: Option<PathBuf>,
+}
+
+impl OpenCLBuilder {
+    pub fn new() -> OpenCLBuilder {
+        OpenCLBuilder {
+            selector: DeviceSelector::Index(0, 0),
+            build_options: vec![],
+            queue_properties: None,
+            warm_up: false,
+            program_cache: None,
+            profiling: false,
+            tracing: false,
+            memory_pool_cap: usize::max_value(),
+            memory_limit: None,
+            zero_copy: false,
+            streams: 0,
+            out_of_order: false,
+            native_gemm: false,
+            #[cfg(feature = "clblast-dynamic")]
+            clblast_library: None,
+        }
+    }
+
+    pub fn device(mut self, platform_id: usize, device_id: usize) -> OpenCLBuilder {
+        self.selector = DeviceSelector::Index(platform_id, device_id);
+        self
+    }
+
+    pub fn selector(mut self, selector: DeviceSelector) -> OpenCLBuilder {
+        self.selector = selector;
+        self
+    }
+
+    pub fn build_option(mut self, option: &str) -> OpenCLBuilder {
+        self.build_options.push(option.to_string());
+        self
+    }
+
+    pub fn fast_relaxed_math(self) -> OpenCLBuilder {
+        self.build_option("-cl-fast-relaxed-math")
+    }
+
+    pub fn mad_enable(self) -> OpenCLBuilder {
+        self.build_option("-cl-mad-enable")
+    }
+
+    pub fn denorms_are_zero(self) -> OpenCLBuilder {
+        self.build_option("-cl-denorms-are-zero")
+    }
+
+    pub fn define(self, name: &str, value: Option<&str>) -> OpenCLBuilder {
+        match value {
+            Some(value) => self.build_option(&format!("-D {}={}", name, value)),
+            None => self.build_option(&format!("-D {}", name)),
+        }
+    }
+
+    pub fn queue_properties(mut self, properties: CommandQueueProperties) -> OpenCLBuilder {
+        self.queue_properties = Some(properties);
+        self
+    }
+
+    pub fn warm_up(mut self, warm_up: bool) -> OpenCLBuilder {
+        self.warm_up = warm_up;
+        self
+    }
+
+    pub fn program_cache<P: AsRef<Path>>(mut self, dir: P) -> OpenCLBuilder {
+        self.program_cache = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn profiling(mut self, profiling: bool) -> OpenCLBuilder {
+        self.profiling = profiling;
+        self
+    }
+
+    pub fn tracing(mut self, tracing: bool) -> OpenCLBuilder {
+        self.tracing = tracing;
+        self
+    }
+
+    pub fn memory_pool_cap(mut self, bytes: usize) -> OpenCLBuilder {
+        self.memory_pool_cap = bytes;
+        self
+    }
+
+    pub fn memory_limit(mut self, bytes: usize) -> OpenCLBuilder {
+        self.memory_limit = Some(bytes);
+        self
+    }
+
+    pub fn zero_copy(mut self, zero_copy: bool) -> OpenCLBuilder {
+        self.zero_copy = zero_copy;
+        self
+    }
+
+    // Number of command queues created in addition to the default one.
+    pub fn streams(mut self, streams: usize) -> OpenCLBuilder {
+        self.streams = streams;
+        self
+    }
+
+    // Ignored on devices without out-of-order execution support.
+    pub fn out_of_order(mut self, out_of_order: bool) -> OpenCLBuilder {
+        self.out_of_order = out_of_order;
+        self
+    }
+
+    // Uses the OpenCL SGEMM kernel even when CLBlast is available.
+    pub fn native_gemm(mut self, native_gemm: bool) -> OpenCLBuilder {
+        self.native_gemm = native_gemm;
+        self
+    }
+
+    // The CLBlast library loaded at runtime, by default `libclblast.so` (or the platform's
+    // equivalent) from the library search path.
+    #[cfg(feature = "clblast-dynamic")]
+    pub fn clblast_library<P: AsRef<Path>>(mut self, path: P) -> OpenCLBuilder {
+        self.clblast_library = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub(crate) fn build_options_string(&self) -> String {
+        self.build_options.join(" ")
+    }
+
+    fn resolve(&self) -> Result<(usize, usize), OpenCLError> {
+        match &self.selector {
+            DeviceSelector::Index(platform_id, device_id) => Ok((*platform_id, *device_id)),
+            selector => {
+                let devices = properties::devices()?;
+                match selector.select(&devices) {
+                    Some(d) => Ok((d.platform_id, d.device_id)),
+                    None => Err(OpenCLError::NoMatchingDevice(selector.clone())),
+                }
+            }
+        }
+    }
+
+    fn finish<'dev>(&self, internal: OpenCLInternal) -> Result<Device<'dev>, OpenCLError> {
+        if self.warm_up {
+            internal.build_all_programs()?;
+        }
+        OpenCL::register_impls(Arc::new(internal))
+    }
+
+    pub fn build<'dev>(self) -> Result<Device<'dev>, OpenCLError> {
+        let (platform_id, device_id) = self.resolve()?;
+        let internal = OpenCLInternal::new(platform_id, device_id, &self)?;
+        self.finish(internal)
+    }
+
+    // Wraps an existing context and queue, e.g. ones shared with other OpenCL code, instead
+    // of creating new ones. Queue properties (profiling, out-of-order) are taken from the
+    // queue. Raw handles can be wrapped with `Context::from_raw_copied_ptr` and
+    // `CommandQueue::from_raw_copied_ptr`.
+    pub fn build_from_queue<'dev>(
+        self,
+        context: &Context,
+        queue: &CommandQueue,
+    ) -> Result<Device<'dev>, OpenCLError> {
+        let internal = OpenCLInternal::from_queue(context, queue, &self)?;
+        self.finish(internal)
+    }
+
+    // Builds one device per entry of `device_ids` (indices on the platform of the selected
+    // device) in a single shared context, so tensors can be copied between them without
+    // going through host memory. An index may be repeated to get several queues on one
+    // device.
+    pub fn build_shared<'dev>(
+        self,
+        device_ids: &[usize],
+    ) -> Result<Vec<Device<'dev>>, OpenCLError> {
+        let (platform_id, _) = self.resolve()?;
+        OpenCLInternal::new_shared(platform_id, device_ids, &self)?
+            .into_iter()
+            .map(|internal| self.finish(internal))
+            .collect()
+    }
+}
+
+impl Default for OpenCLBuilder {
+    fn default() -> OpenCLBuilder {
+        OpenCLBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OpenCLBuilder;
+    use crate::{DeviceSelector, OpenCL};
+    use ocl_core::{ContextProperties, Event, ProgramBuildInfo, ProgramBuildInfoResult};
+    use prima_undine::functions::BasicFunctions;
+    use prima_undine::shape;
+
+    #[test]
+    fn check_build_options_string() {
+        let builder = OpenCLBuilder::new()
+            .fast_relaxed_math()
+            .mad_enable()
+            .denorms_are_zero()
+            .define("FOO", None)
+            .define("BAR", Some("2"));
+        assert_eq!(
+            "-cl-fast-relaxed-math -cl-mad-enable -cl-denorms-are-zero -D FOO -D BAR=2",
+            builder.build_options_string()
+        );
+        assert_eq!("", OpenCLBuilder::new().build_options_string());
+    }
+
+    #[test]
+    fn check_build_with_fast_math() {
+        let x_data = vec![1000., 100., 10., 1., 0.1, 0.01, 0.001, 0.0001];
+        let k = 1.;
+        let y_data = vec![1001., 101., 11., 2., 1.1, 1.01, 1.001, 1.0001];
+        let dev = OpenCLBuilder::new()
+            .selector(DeviceSelector::from_env().unwrap())
+            .fast_relaxed_math()
+            .mad_enable()
+            .define("PRIMA_UNDINE_TEST", Some("1"))
+            .build()
+            .unwrap();
+        let x = dev.new_tensor_by_slice(shape![2, 2; 2], &x_data);
+        let mut y = dev.new_tensor(shape![2, 2; 2]);
+        y.alloc();
+        dev.call_fw_impl("add_const_fw_impl", &[&x], &[], &[k], &mut [&mut y]);
+        assert_vector_ulps_eq!(y_data, y.to_vec(), max_ulps = 4);
+
+        // The options must have reached the compiler, not just the builder.
+        let internal = &OpenCL::from_device(&dev).unwrap().internal;
+        let program = internal.program("add").unwrap();
+        let device = internal.queue.device().unwrap();
+        let options = match ocl_core::get_program_build_info(
+            &program,
+            &device,
+            ProgramBuildInfo::BuildOptions,
+        )
+        .unwrap()
+        {
+            ProgramBuildInfoResult::BuildOptions(options) => options,
+            _ => panic!(),
+        };
+        for option in &[
+            "-cl-fast-relaxed-math",
+            "-cl-mad-enable",
+            "-D PRIMA_UNDINE_TEST=1",
+        ] {
+            assert!(options.contains(option));
+        }
+    }
+
+    #[test]
+    fn check_build_from_queue() {
+        let properties = OpenCL::from_device(crate::test_utils::get_device())
+            .unwrap()
+            .properties()
+            .clone();
+        let platform = ocl_core::get_platform_ids().unwrap()[properties.platform_id];
+        let device = ocl_core::get_device_ids(&platform, None, None).unwrap()[properties.device_id];
+        let context_properties = ContextProperties::new().platform(platform);
+        let context =
+            ocl_core::create_context(Some(&context_properties), &[device], None, None).unwrap();
+        let queue = ocl_core::create_command_queue(&context, &device, None).unwrap();
+
+        let dev = OpenCLBuilder::new()
+            .build_from_queue(&context, &queue)
+            .unwrap();
+        let cl = OpenCL::from_device(&dev).unwrap();
+        assert_eq!(properties.name, cl.properties().name);
+        let x = dev.new_tensor_by_slice(shape![2, 2], &[1., 2., 3., 4.]);
+        let mut y = dev.new_tensor(shape![2, 2]);
+        y.alloc();
+        dev.call_fw_impl("neg_fw_impl", &[&x], &[], &[], &mut [&mut y]);
+        // The same queue keeps working for code outside of this crate.
+        let mut data = vec![0f32; 4];
+        unsafe {
+            ocl_core::enqueue_read_buffer(
+                &queue,
+                cl.buffer(&y),
+                true,
+                0,
+                &mut data,
+                None::<Event>,
+                None::<&mut Event>,
+            )
+            .unwrap();
+        }
+        assert_vector_ulps_eq!(vec![-1., -2., -3., -4.], data);
+    }
+}
@@ -0,0 +1,181 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use ocl_core::{DeviceId, DeviceInfo, DeviceInfoResult};
+
+use crate::OpenCLError;
+
+const MAGIC: &[u8; 8] = b"PUCLBIN1";
+
+fn fnv1a(data: &[u8]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for &b in data {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+fn read_u64(data: &[u8], pos: usize) -> Option<u64> {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(data.get(pos..pos + 8)?);
+    Some(u64::from_le_bytes(buf))
+}
+
+// Layout: MAGIC, key length, key, binary length, binary hash, binary.
+fn encode(key: &str, binary: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(MAGIC.len() + key.len() + binary.len() + 24);
+    data.extend_from_slice(MAGIC);
+    data.extend_from_slice(&(key.len() as u64).to_le_bytes());
+    data.extend_from_slice(key.as_bytes());
+    data.extend_from_slice(&(binary.len() as u64).to_le_bytes());
+    data.extend_from_slice(&fnv1a(binary).to_le_bytes());
+    data.extend_from_slice(binary);
+    data
+}
+
+fn decode(key: &str, data: &[u8]) -> Option<Vec<u8>> {
+    if data.get(..MAGIC.len())? != MAGIC {
+        return None;
+    }
+    let mut pos = MAGIC.len();
+    let key_len = read_u64(data, pos)? as usize;
+    pos += 8;
+    if data.get(pos..pos.checked_add(key_len)?)? != key.as_bytes() {
+        return None;
+    }
+    pos += key_len;
+    let binary_len = read_u64(data, pos)? as usize;
+    let binary_hash = read_u64(data, pos + 8)?;
+    pos += 16;
+    let binary = data.get(pos..)?;
+    if binary.len() != binary_len || fnv1a(binary) != binary_hash {
+        return None;
+    }
+    Some(binary.to_vec())
+}
+
+pub struct ProgramCache {
+    dir: PathBuf,
+    device_key: String,
+}
+
+impl ProgramCache {
+    pub fn new(
+        dir: &Path,
+        device: &DeviceId,
+        build_options: &str,
+    ) -> Result<ProgramCache, OpenCLError> {
+        let info = |request| {
+            ocl_core::get_device_info(device, request).map_err(OpenCLError::PlatformQuery)
+        };
+        let name = match info(DeviceInfo::Name)? {
+            DeviceInfoResult::Name(name) => name,
+            _ => panic!(),
+        };
+        let driver_version = match info(DeviceInfo::DriverVersion)? {
+            DeviceInfoResult::DriverVersion(version) => version,
+            _ => panic!(),
+        };
+        Ok(ProgramCache {
+            dir: dir.to_path_buf(),
+            device_key: format!("{}\n{}\n{}", name, driver_version, build_options),
+        })
+    }
+
+    fn entry(&self, src: &str) -> (PathBuf, String) {
+        let key = format!("{}\n{:016x}", self.device_key, fnv1a(src.as_bytes()));
+        let path = self.dir.join(format!("{:016x}.bin", fnv1a(key.as_bytes())));
+        (path, key)
+    }
+
+    pub fn load(&self, src: &str) -> Option<Vec<u8>> {
+        let (path, key) = self.entry(src);
+        decode(&key, &fs::read(path).ok()?)
+    }
+
+    pub fn store(&self, src: &str, binary: &[u8]) {
+        let (path, key) = self.entry(src);
+        let tmp_path = path.with_extension(format!("tmp{}", std::process::id()));
+        // The cache is an optimization only, so I/O failures are ignored.
+        let result = fs::create_dir_all(&self.dir)
+            .and_then(|_| fs::File::create(&tmp_path))
+            .and_then(|mut f| f.write_all(&encode(&key, binary)))
+            .and_then(|_| fs::rename(&tmp_path, &path));
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode};
+    use crate::{DeviceSelector, OpenCLBuilder};
+    use prima_undine::functions::BasicFunctions;
+    use prima_undine::shape;
+    use std::env;
+    use std::fs;
+    use std::time::SystemTime;
+
+    #[test]
+    fn check_encode_decode() {
+        let binary = vec![1, 2, 3, 4, 5, 6, 7, 8, 9];
+        let data = encode("key", &binary);
+        assert_eq!(Some(binary.clone()), decode("key", &data));
+        assert_eq!(None, decode("other key", &data));
+        let mut corrupted = data.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert_eq!(None, decode("key", &corrupted));
+        assert_eq!(None, decode("key", &data[..data.len() - 1]));
+        assert_eq!(None, decode("key", &[]));
+    }
+
+    #[test]
+    fn check_program_cache() {
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir = env::temp_dir().join(format!("prima_undine_opencl_cache_{}", nanos));
+        let x_data = vec![1., -2., 3., -4.];
+        let y_data = vec![-1., 2., -3., 4.];
+        let run = || {
+            let dev = OpenCLBuilder::new()
+                .selector(DeviceSelector::from_env().unwrap())
+                .program_cache(&dir)
+                .build()
+                .unwrap();
+            let x = dev.new_tensor_by_slice(shape![2, 2], &x_data);
+            let mut y = dev.new_tensor(shape![2, 2]);
+            y.alloc();
+            dev.call_fw_impl("neg_fw_impl", &[&x], &[], &[], &mut [&mut y]);
+            assert_vector_ulps_eq!(y_data, y.to_vec());
+        };
+        let entries = || {
+            fs::read_dir(&dir)
+                .unwrap()
+                .map(|e| e.unwrap().path())
+                .collect::<Vec<_>>()
+        };
+
+        // cold cache
+        run();
+        let paths = entries();
+        assert_eq!(1, paths.len());
+        let original = fs::read(&paths[0]).unwrap();
+
+        // warm cache
+        run();
+        assert_eq!(original, fs::read(&paths[0]).unwrap());
+
+        // corrupted entry
+        fs::write(&paths[0], &original[..original.len() / 2]).unwrap();
+        run();
+        assert_eq!(1, entries().len());
+        assert!(fs::read(&paths[0]).unwrap().len() > original.len() / 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,8 +1,59 @@
 use std::ffi::c_void;
+#[cfg(feature = "clblast-dynamic")]
+use std::path::Path;
 
+#[cfg(feature = "clblast-dynamic")]
+use libloading::Library;
+
+type SgemmFn = unsafe extern "C" fn(
+    layout: i32,
+    a_transpose: i32,
+    b_transpose: i32,
+    m: usize,
+    n: usize,
+    k: usize,
+    alpha: f32,
+    a_buffer: *const c_void,
+    a_offset: usize,
+    a_ld: usize,
+    b_buffer: *const c_void,
+    b_offset: usize,
+    b_ld: usize,
+    beta: f32,
+    c_buffer: *mut c_void,
+    c_offset: usize,
+    c_ld: usize,
+    queue: *mut *mut c_void,
+    event: *mut *mut c_void,
+) -> i32;
+
+type SgemmBatchedFn = unsafe extern "C" fn(
+    layout: i32,
+    a_transpose: i32,
+    b_transpose: i32,
+    m: usize,
+    n: usize,
+    k: usize,
+    alphas: *const f32,
+    a_buffer: *const c_void,
+    a_offsets: *const usize,
+    a_ld: usize,
+    b_buffer: *const c_void,
+    b_offsets: *const usize,
+    b_ld: usize,
+    betas: *const f32,
+    c_buffer: *mut c_void,
+    c_offsets: *const usize,
+    c_ld: usize,
+    batch_count: usize,
+    queue: *mut *mut c_void,
+    event: *mut *mut c_void,
+) -> i32;
+
+#[cfg(all(feature = "clblast", not(feature = "clblast-dynamic")))]
 #[link(name = "clblast", kind = "dylib")]
 extern "C" {
-    pub fn CLBlastSgemm(
+    fn CLBlastSgemm(
         layout: i32,
         a_transpose: i32,
         b_transpose: i32,
@@ -24,7 +75,7 @@ extern "C" {
         event: *mut *mut c_void,
     ) -> i32;
 
-    pub fn CLBlastSgemmBatched(
+    fn CLBlastSgemmBatched(
         layout: i32,
         a_transpose: i32,
         b_transpose: i32,
@@ -48,6 +99,53 @@ extern "C" {
     ) -> i32;
 }
 
+// The CLBlast entry points, either linked at build time or resolved from a shared library.
+pub struct ClBlast {
+    pub sgemm: SgemmFn,
+    pub sgemm_batched: SgemmBatchedFn,
+    #[cfg(feature = "clblast-dynamic")]
+    _library: Library,
+}
+
+impl ClBlast {
+    #[cfg(all(feature = "clblast", not(feature = "clblast-dynamic")))]
+    pub fn linked() -> ClBlast {
+        ClBlast {
+            sgemm: CLBlastSgemm,
+            sgemm_batched: CLBlastSgemmBatched,
+        }
+    }
+
+    #[cfg(feature = "clblast-dynamic")]
+    pub fn default_library() -> &'static str {
+        if cfg!(target_os = "windows") {
+            "clblast.dll"
+        } else if cfg!(target_os = "macos") {
+            "libclblast.dylib"
+        } else {
+            "libclblast.so"
+        }
+    }
+
+    #[cfg(feature = "clblast-dynamic")]
+    pub fn load(path: &Path) -> Result<ClBlast, String> {
+        let library = Library::new(path).map_err(|e| e.to_string())?;
+        unsafe {
+            let sgemm = *library
+                .get::<SgemmFn>(b"CLBlastSgemm\0")
+                .map_err(|e| e.to_string())?;
+            let sgemm_batched = *library
+                .get::<SgemmBatchedFn>(b"CLBlastSgemmBatched\0")
+                .map_err(|e| e.to_string())?;
+            Ok(ClBlast {
+                sgemm: sgemm,
+                sgemm_batched: sgemm_batched,
+                _library: library,
+            })
+        }
+    }
+}
+
 #[allow(dead_code)]
 pub mod layout {
     pub const ROW_MAJOR: i32 = 101;
@@ -0,0 +1,236 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use ocl_core::Event;
+
+#[derive(Default)]
+struct MemState {
+    last_write: Option<Event>,
+    reads: Vec<Event>,
+}
+
+// The buffers touched by the impl call running on this thread. Commands inside one call
+// are chained, so the last one stands for all of them once the call returns.
+struct Access {
+    owner: usize,
+    reads: Vec<usize>,
+    writes: Vec<usize>,
+    deps: Vec<Event>,
+    last: Option<Event>,
+}
+
+thread_local! {
+    static CURRENT_ACCESS: RefCell<Vec<Access>> = RefCell::new(vec![]);
+}
+
+// Tracks the last write and the pending reads of each `cl_mem` for out-of-order queues.
+pub struct DependencyTracker {
+    state: Mutex<HashMap<usize, MemState>>,
+}
+
+impl DependencyTracker {
+    pub fn new() -> DependencyTracker {
+        DependencyTracker {
+            state: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_access<R, F: FnOnce() -> R>(
+        &self,
+        owner: usize,
+        reads: Vec<usize>,
+        writes: Vec<usize>,
+        f: F,
+    ) -> R {
+        let deps = {
+            let state = self.state.lock().unwrap();
+            let mut deps = vec![];
+            for mem in &reads {
+                if let Some(s) = state.get(mem) {
+                    deps.extend(s.last_write.iter().cloned());
+                }
+            }
+            for mem in &writes {
+                if let Some(s) = state.get(mem) {
+                    deps.extend(s.last_write.iter().cloned());
+                    deps.extend(s.reads.iter().cloned());
+                }
+            }
+            deps
+        };
+        CURRENT_ACCESS.with(|current| {
+            let mut current = current.borrow_mut();
+            // A nested access also waits for what the enclosing call is waiting for.
+            let mut deps = deps;
+            if let Some(outer) = current.last().filter(|outer| outer.owner == owner) {
+                match &outer.last {
+                    Some(last) => deps.push(last.clone()),
+                    None => deps.extend(outer.deps.iter().cloned()),
+                }
+            }
+            current.push(Access {
+                owner: owner,
+                reads: reads,
+                writes: writes,
+                deps: deps,
+                last: None,
+            })
+        });
+        let ret = f();
+        let access = CURRENT_ACCESS.with(|current| {
+            let mut current = current.borrow_mut();
+            let access = current.pop().unwrap();
+            if let Some(outer) = current.last_mut().filter(|outer| outer.owner == owner) {
+                if access.last.is_some() {
+                    outer.last = access.last.clone();
+                }
+            }
+            access
+        });
+        if let Some(last) = access.last {
+            self.commit(&access.reads, &access.writes, &last);
+        }
+        ret
+    }
+
+    fn commit(&self, reads: &[usize], writes: &[usize], last: &Event) {
+        let mut state = self.state.lock().unwrap();
+        for mem in reads.iter().filter(|mem| !writes.contains(mem)) {
+            let s = state.entry(*mem).or_insert_with(MemState::default);
+            s.reads
+                .retain(|e| !ocl_core::event_is_complete(e).unwrap_or(true));
+            s.reads.push(last.clone());
+        }
+        for mem in writes {
+            let s = state.entry(*mem).or_insert_with(MemState::default);
+            s.last_write = Some(last.clone());
+            s.reads.clear();
+        }
+    }
+
+    // Events the next command must wait for, or `None` outside of any impl call.
+    pub fn wait_list(&self, owner: usize) -> Option<Vec<Event>> {
+        CURRENT_ACCESS.with(|current| match current.borrow().last() {
+            Some(access) if access.owner == owner => Some(match &access.last {
+                Some(last) => vec![last.clone()],
+                None => access.deps.clone(),
+            }),
+            _ => None,
+        })
+    }
+
+    // Drops the state of a buffer released to the driver, whose address may be reused.
+    pub fn forget(&self, mem: usize) {
+        self.state.lock().unwrap().remove(&mem);
+    }
+
+    pub fn record(&self, owner: usize, event: &Event) {
+        CURRENT_ACCESS.with(|current| {
+            if let Some(access) = current.borrow_mut().last_mut() {
+                if access.owner == owner {
+                    access.last = Some(event.clone());
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DeviceSelector, OpenCL, OpenCLBuilder};
+    use prima_undine::functions::BasicFunctions;
+    use prima_undine::shape;
+
+    #[test]
+    fn check_out_of_order_execution() {
+        let dev = OpenCLBuilder::new()
+            .selector(DeviceSelector::from_env().unwrap())
+            .out_of_order(true)
+            .build()
+            .unwrap();
+        let cl = OpenCL::from_device(&dev).unwrap();
+        assert_eq!(
+            cl.properties()
+                .queue_properties
+                .contains(ocl_core::QUEUE_OUT_OF_ORDER_EXEC_MODE_ENABLE),
+            cl.out_of_order()
+        );
+        let n = 64 * 64;
+        let a_data = (0..n).map(|i| (i % 7) as f32).collect::<Vec<_>>();
+        let b_data = (0..n).map(|i| (i % 5) as f32).collect::<Vec<_>>();
+        let a = dev.new_tensor_by_slice(shape![64, 64], &a_data);
+        let b = dev.new_tensor_by_slice(shape![64, 64], &b_data);
+        let gy = dev.new_tensor_by_constant(shape![64, 64], 1.);
+
+        // y = a + b; z = y * gy (matmul); independent gradients of add
+        let mut y = dev.new_tensor(shape![64, 64]);
+        y.alloc();
+        dev.call_fw_impl("add_fw_impl", &[&a, &b], &[], &[], &mut [&mut y]);
+        let mut z = dev.new_tensor(shape![64, 64]);
+        z.alloc();
+        dev.call_fw_impl("matmul_fw_impl", &[&y, &gy], &[], &[], &mut [&mut z]);
+        let mut ga = dev.new_tensor_by_constant(shape![64, 64], 0.);
+        let mut gb = dev.new_tensor_by_constant(shape![64, 64], 1.);
+        dev.call_bw_impl("add_bw_a_impl", &[&a, &b], &[&y], &[&z], &[], &[], &mut ga);
+        dev.call_bw_impl("add_bw_b_impl", &[&a, &b], &[&y], &[&z], &[], &[], &mut gb);
+
+        // Overwrite an input only after its readers are done.
+        dev.call_fw_impl("reset_tensor_impl", &[], &[], &[0.], &mut [&mut y]);
+
+        let y_data = a_data
+            .iter()
+            .zip(&b_data)
+            .map(|(a, b)| a + b)
+            .collect::<Vec<_>>();
+        let z_data = (0..n)
+            .map(|i| (0..64).map(|k| y_data[i % 64 + k * 64]).sum::<f32>())
+            .collect::<Vec<_>>();
+        assert_vector_ulps_eq!(z_data, z.to_vec());
+        assert_vector_ulps_eq!(z_data, ga.to_vec());
+        assert_vector_ulps_eq!(
+            z_data.iter().map(|z| z + 1.).collect::<Vec<_>>(),
+            gb.to_vec()
+        );
+        assert_vector_ulps_eq!(vec![0.; n], y.to_vec());
+        assert_vector_ulps_eq!(a_data, cl.view(&a).unwrap().to_vec());
+    }
+
+    #[test]
+    fn check_released_buffers_are_forgotten() {
+        let dev = OpenCLBuilder::new()
+            .selector(DeviceSelector::from_env().unwrap())
+            .out_of_order(true)
+            .memory_pool_cap(0)
+            .build()
+            .unwrap();
+        let cl = OpenCL::from_device(&dev).unwrap();
+        let dependencies = match &cl.internal.dependencies {
+            Some(dependencies) => dependencies,
+            None => return,
+        };
+        {
+            let mut x = dev.new_tensor_by_constant(shape![2, 2], 1.);
+            dev.call_fw_impl("reset_tensor_impl", &[], &[], &[0.], &mut [&mut x]);
+            assert!(!dependencies.state.lock().unwrap().is_empty());
+        }
+        assert!(dependencies.state.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn check_out_of_order_random() {
+        let dev = OpenCLBuilder::new()
+            .selector(DeviceSelector::from_env().unwrap())
+            .out_of_order(true)
+            .build()
+            .unwrap();
+        let mut x = dev.new_tensor(shape![1024]);
+        let mut y = dev.new_tensor(shape![1024]);
+        x.alloc();
+        y.alloc();
+        dev.call_fw_impl("random_uniform_impl", &[], &[], &[0., 1.], &mut [&mut x]);
+        dev.call_fw_impl("random_uniform_impl", &[], &[], &[0., 1.], &mut [&mut y]);
+        // Both calls advance the same generator state, so they never repeat each other.
+        assert_ne!(x.to_vec(), y.to_vec());
+    }
+}
@@ -0,0 +1,324 @@
+use std::error;
+use std::fmt;
+
+use crate::DeviceSelector;
+
+#[derive(Debug)]
+pub enum OpenCLError {
+    NoPlatform,
+    PlatformOutOfRange {
+        index: usize,
+        count: usize,
+    },
+    DeviceOutOfRange {
+        index: usize,
+        count: usize,
+    },
+    NoMatchingDevice(DeviceSelector),
+    InvalidEnvironment {
+        name: &'static str,
+        value: String,
+    },
+    PlatformQuery(ocl_core::Error),
+    ContextCreation(ocl_core::Error),
+    QueueCreation(ocl_core::Error),
+    KernelBuild {
+        program: String,
+        log: String,
+        error: ocl_core::Error,
+    },
+    KernelCreation {
+        kernel: &'static str,
+        error: ocl_core::Error,
+    },
+    KernelArgument {
+        kernel: &'static str,
+        index: u32,
+        error: ocl_core::Error,
+    },
+    Queue(ocl_core::Error),
+    Profiling(ocl_core::Error),
+    Allocation(ocl_core::Error),
+    OutOfMemory {
+        requested: usize,
+        allocated: usize,
+        limit: Option<usize>,
+    },
+    BufferQuery(ocl_core::Error),
+    ForeignBuffer,
+    BufferTooSmall {
+        required: usize,
+        size: usize,
+    },
+    UnknownDevice,
+    InvalidLeadDim {
+        matrix: char,
+        ld: usize,
+        rows: usize,
+    },
+    InsufficientMemory {
+        matrix: char,
+        required: usize,
+        size: usize,
+    },
+    ClBlast(ClBlastError),
+}
+
+impl fmt::Display for OpenCLError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OpenCLError::NoPlatform => write!(f, "no OpenCL platform is available"),
+            OpenCLError::PlatformOutOfRange { index, count } => write!(
+                f,
+                "platform index {} is out of range ({} platform(s) available)",
+                index, count
+            ),
+            OpenCLError::DeviceOutOfRange { index, count } => write!(
+                f,
+                "device index {} is out of range ({} device(s) available)",
+                index, count
+            ),
+            OpenCLError::NoMatchingDevice(selector) => {
+                write!(f, "no OpenCL device matches {:?}", selector)
+            }
+            OpenCLError::InvalidEnvironment { name, value } => {
+                write!(f, "invalid value for {}: {:?}", name, value)
+            }
+            OpenCLError::PlatformQuery(e) => {
+                write!(f, "failed to query OpenCL platforms or devices: {}", e)
+            }
+            OpenCLError::ContextCreation(e) => write!(f, "failed to create context: {}", e),
+            OpenCLError::QueueCreation(e) => write!(f, "failed to create command queue: {}", e),
+            OpenCLError::KernelBuild {
+                program,
+                log,
+                error,
+            } => {
+                write!(f, "failed to build kernel program `{}`: {}", program, error)?;
+                if !log.is_empty() {
+                    write!(f, "\nbuild log:\n{}", log)?;
+                }
+                Ok(())
+            }
+            OpenCLError::KernelCreation { kernel, error } => {
+                write!(f, "failed to create kernel `{}`: {}", kernel, error)
+            }
+            OpenCLError::KernelArgument {
+                kernel,
+                index,
+                error,
+            } => write!(
+                f,
+                "failed to set argument {} of kernel `{}`: {}",
+                index, kernel, error
+            ),
+            OpenCLError::Queue(e) => write!(f, "command queue operation failed: {}", e),
+            OpenCLError::Profiling(e) => write!(f, "failed to read profiling info: {}", e),
+            OpenCLError::Allocation(e) => write!(f, "failed to allocate device buffer: {}", e),
+            OpenCLError::OutOfMemory {
+                requested,
+                allocated,
+                limit,
+            } => {
+                write!(
+                    f,
+                    "out of device memory: requested {} bytes with {} bytes in use",
+                    requested, allocated
+                )?;
+                if let Some(limit) = limit {
+                    write!(f, " (limit {} bytes)", limit)?;
+                }
+                Ok(())
+            }
+            OpenCLError::BufferQuery(e) => write!(f, "failed to query buffer: {}", e),
+            OpenCLError::ForeignBuffer => {
+                write!(f, "buffer belongs to a different context than the device")
+            }
+            OpenCLError::BufferTooSmall { required, size } => write!(
+                f,
+                "buffer of {} bytes is too small for a tensor of {} bytes",
+                size, required
+            ),
+            OpenCLError::InvalidLeadDim { matrix, ld, rows } => write!(
+                f,
+                "leading dimension {} of matrix {} is smaller than its {} rows",
+                ld, matrix, rows
+            ),
+            OpenCLError::InsufficientMemory {
+                matrix,
+                required,
+                size,
+            } => write!(
+                f,
+                "buffer of {} bytes is too small for matrix {} of {} bytes",
+                size, matrix, required
+            ),
+            OpenCLError::UnknownDevice => {
+                write!(f, "the queue's device is not listed by any OpenCL platform")
+            }
+            OpenCLError::ClBlast(e) => write!(f, "CLBlast failed: {}", e),
+        }
+    }
+}
+
+impl error::Error for OpenCLError {}
+
+// Status codes returned by CLBlast routines (`clblast::StatusCode`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClBlastError {
+    TempBufferAllocFailure,
+    OutOfResources,
+    OutOfHostMemory,
+    NotImplemented,
+    InvalidMatrixA,
+    InvalidMatrixB,
+    InvalidMatrixC,
+    InvalidDimension,
+    InvalidLeadDimA,
+    InvalidLeadDimB,
+    InvalidLeadDimC,
+    InsufficientMemoryA,
+    InsufficientMemoryB,
+    InsufficientMemoryC,
+    InsufficientMemoryTemp,
+    InvalidBatchCount,
+    InvalidLocalMemUsage,
+    DatabaseError,
+    // Another OpenCL error code passed through by CLBlast.
+    OpenCL(i32),
+    Unknown(i32),
+}
+
+impl ClBlastError {
+    pub fn check(status: i32) -> Result<(), ClBlastError> {
+        Err(match status {
+            0 => return Ok(()),
+            -4 => ClBlastError::TempBufferAllocFailure,
+            -5 => ClBlastError::OutOfResources,
+            -6 => ClBlastError::OutOfHostMemory,
+            -1024 => ClBlastError::NotImplemented,
+            -1022 => ClBlastError::InvalidMatrixA,
+            -1021 => ClBlastError::InvalidMatrixB,
+            -1020 => ClBlastError::InvalidMatrixC,
+            -1017 => ClBlastError::InvalidDimension,
+            -1016 => ClBlastError::InvalidLeadDimA,
+            -1015 => ClBlastError::InvalidLeadDimB,
+            -1014 => ClBlastError::InvalidLeadDimC,
+            -1011 => ClBlastError::InsufficientMemoryA,
+            -1010 => ClBlastError::InsufficientMemoryB,
+            -1009 => ClBlastError::InsufficientMemoryC,
+            -2050 => ClBlastError::InsufficientMemoryTemp,
+            -2049 => ClBlastError::InvalidBatchCount,
+            -2046 => ClBlastError::InvalidLocalMemUsage,
+            -2041 => ClBlastError::DatabaseError,
+            -99..=-1 => ClBlastError::OpenCL(status),
+            _ => ClBlastError::Unknown(status),
+        })
+    }
+}
+
+impl fmt::Display for ClBlastError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClBlastError::TempBufferAllocFailure => {
+                write!(f, "failed to allocate a temporary buffer")
+            }
+            ClBlastError::OutOfResources => write!(f, "out of device resources"),
+            ClBlastError::OutOfHostMemory => write!(f, "out of host memory"),
+            ClBlastError::NotImplemented => write!(f, "routine or option not implemented"),
+            ClBlastError::InvalidMatrixA => write!(f, "invalid matrix A"),
+            ClBlastError::InvalidMatrixB => write!(f, "invalid matrix B"),
+            ClBlastError::InvalidMatrixC => write!(f, "invalid matrix C"),
+            ClBlastError::InvalidDimension => write!(f, "zero or negative matrix dimension"),
+            ClBlastError::InvalidLeadDimA => write!(f, "invalid leading dimension of matrix A"),
+            ClBlastError::InvalidLeadDimB => write!(f, "invalid leading dimension of matrix B"),
+            ClBlastError::InvalidLeadDimC => write!(f, "invalid leading dimension of matrix C"),
+            ClBlastError::InsufficientMemoryA => write!(f, "insufficient memory for matrix A"),
+            ClBlastError::InsufficientMemoryB => write!(f, "insufficient memory for matrix B"),
+            ClBlastError::InsufficientMemoryC => write!(f, "insufficient memory for matrix C"),
+            ClBlastError::InsufficientMemoryTemp => {
+                write!(f, "insufficient memory for a temporary buffer")
+            }
+            ClBlastError::InvalidBatchCount => write!(f, "invalid batch count"),
+            ClBlastError::InvalidLocalMemUsage => {
+                write!(f, "kernel requires more local memory than available")
+            }
+            ClBlastError::DatabaseError => write!(f, "no tuning parameters for the device"),
+            ClBlastError::OpenCL(status) => write!(f, "OpenCL error {}", status),
+            ClBlastError::Unknown(status) => write!(f, "unknown status {}", status),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClBlastError, OpenCLError};
+    use crate::{DeviceSelector, OpenCL, OpenCLBuilder};
+
+    #[test]
+    fn check_try_new_device_out_of_range() {
+        match OpenCL::try_new(0, usize::max_value()) {
+            Err(OpenCLError::DeviceOutOfRange { index, .. }) => {
+                assert_eq!(usize::max_value(), index)
+            }
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("device must not be created"),
+        }
+    }
+
+    #[test]
+    fn check_try_new_platform_out_of_range() {
+        match OpenCL::try_new(usize::max_value(), 0) {
+            Err(OpenCLError::PlatformOutOfRange { index, .. }) => {
+                assert_eq!(usize::max_value(), index)
+            }
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("device must not be created"),
+        }
+    }
+
+    #[test]
+    fn check_kernel_build_log() {
+        let dev = OpenCLBuilder::new()
+            .selector(DeviceSelector::from_env().unwrap())
+            .build()
+            .unwrap();
+        let internal = OpenCL::from_device(&dev).unwrap().internal;
+        let src = "kernel void broken_kernel(global float *px) { px[0] = undefined_symbol; }";
+        match internal.build_program("common + broken", src) {
+            Err(OpenCLError::KernelBuild { program, log, .. }) => {
+                assert_eq!("common + broken", program);
+                assert!(log.contains("undefined_symbol"));
+            }
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("program must not be built"),
+        }
+    }
+
+    #[test]
+    fn check_clblast_status() {
+        assert_eq!(Ok(()), ClBlastError::check(0));
+        assert_eq!(Err(ClBlastError::OutOfResources), ClBlastError::check(-5));
+        assert_eq!(
+            Err(ClBlastError::InvalidLeadDimA),
+            ClBlastError::check(-1016)
+        );
+        assert_eq!(
+            Err(ClBlastError::InsufficientMemoryC),
+            ClBlastError::check(-1009)
+        );
+        assert_eq!(
+            Err(ClBlastError::InvalidBatchCount),
+            ClBlastError::check(-2049)
+        );
+        assert_eq!(Err(ClBlastError::OpenCL(-38)), ClBlastError::check(-38));
+        assert_eq!(
+            Err(ClBlastError::Unknown(-2040)),
+            ClBlastError::check(-2040)
+        );
+        assert_eq!(
+            "CLBlast failed: invalid leading dimension of matrix A",
+            OpenCLError::ClBlast(ClBlastError::InvalidLeadDimA).to_string()
+        );
+    }
+}
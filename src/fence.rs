@@ -0,0 +1,53 @@
+use ocl_core::types::abs::{CommandQueue, Event};
+
+use crate::OpenCLError;
+
+// Completes once every command enqueued before it has finished.
+pub struct Fence {
+    event: Event,
+}
+
+impl Fence {
+    pub(crate) fn new(queue: &CommandQueue) -> Result<Fence, OpenCLError> {
+        let mut event = Event::null();
+        ocl_core::enqueue_marker_with_wait_list(queue, None::<Event>, Some(&mut event), None)
+            .map_err(OpenCLError::Queue)?;
+        ocl_core::flush(queue).map_err(OpenCLError::Queue)?;
+        Ok(Fence { event: event })
+    }
+
+    pub fn is_complete(&self) -> Result<bool, OpenCLError> {
+        ocl_core::event_is_complete(&self.event).map_err(OpenCLError::Queue)
+    }
+
+    pub fn wait(&self) -> Result<(), OpenCLError> {
+        ocl_core::wait_for_event(&self.event).map_err(OpenCLError::Queue)
+    }
+
+    pub(crate) fn event(&self) -> &Event {
+        &self.event
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::OpenCL;
+    use prima_undine::functions::BasicFunctions;
+    use prima_undine::shape;
+
+    #[test]
+    fn check_fence() {
+        let dev = OpenCL::from_env().unwrap();
+        let cl = OpenCL::from_device(&dev).unwrap();
+        let x = dev.new_tensor_by_constant(shape![256, 256], 1.);
+        let mut y = dev.new_tensor(shape![256, 256]);
+        y.alloc();
+        dev.call_fw_impl("matmul_fw_impl", &[&x, &x], &[], &[], &mut [&mut y]);
+        let fence = cl.fence().unwrap();
+        fence.wait().unwrap();
+        assert!(fence.is_complete().unwrap());
+        cl.flush().unwrap();
+        cl.synchronize().unwrap();
+        assert_vector_ulps_eq!(vec![256.; 256 * 256], y.to_vec());
+    }
+}
@@ -0,0 +1,547 @@
+use std::fmt;
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use ocl_core::{ArgVal, Kernel, Mem, MemInfo, MemInfoResult};
+
+#[cfg(any(feature = "clblast", feature = "clblast-dynamic"))]
+use crate::clblast::{self, ClBlast};
+use crate::lazy::Lazy;
+use crate::profiler;
+use crate::tracer::Command;
+#[cfg(any(feature = "clblast", feature = "clblast-dynamic"))]
+use crate::ClBlastError;
+use crate::{OpenCLError, OpenCLInternal};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GemmBackend {
+    ClBlast,
+    Native,
+}
+
+impl fmt::Display for GemmBackend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GemmBackend::ClBlast => write!(f, "CLBlast"),
+            GemmBackend::Native => write!(f, "native"),
+        }
+    }
+}
+
+// Must match the work group size of `sgemm_kernel`.
+const TILE: usize = 16;
+
+// A column-major matrix in a buffer. Batch element `n` starts at `offset + n * stride`.
+#[derive(Clone, Copy)]
+pub struct Operand<'a> {
+    pub buffer: &'a Mem,
+    pub offset: usize,
+    pub ld: usize,
+    pub stride: usize,
+}
+
+impl<'a> Operand<'a> {
+    pub fn new(buffer: &'a Mem, ld: usize, stride: usize) -> Operand<'a> {
+        Operand {
+            buffer: buffer,
+            offset: 0,
+            ld: ld,
+            stride: stride,
+        }
+    }
+
+    pub fn offset(self, offset: usize) -> Operand<'a> {
+        Operand {
+            offset: offset,
+            ..self
+        }
+    }
+
+    // Checks that a `rows x cols` matrix repeated `batch` times fits in the buffer.
+    fn check(
+        &self,
+        matrix: char,
+        rows: usize,
+        cols: usize,
+        batch: usize,
+    ) -> Result<(), OpenCLError> {
+        if self.ld < rows.max(1) {
+            return Err(OpenCLError::InvalidLeadDim {
+                matrix: matrix,
+                ld: self.ld,
+                rows: rows,
+            });
+        }
+        if rows == 0 || cols == 0 || batch == 0 {
+            return Ok(());
+        }
+        let size = match ocl_core::get_mem_object_info(self.buffer, MemInfo::Size)
+            .map_err(OpenCLError::BufferQuery)?
+        {
+            MemInfoResult::Size(size) => size,
+            _ => panic!(),
+        };
+        let required = (self.offset + (batch - 1) * self.stride + self.ld * (cols - 1) + rows)
+            * mem::size_of::<f32>();
+        if size < required {
+            return Err(OpenCLError::InsufficientMemory {
+                matrix: matrix,
+                required: required,
+                size: size,
+            });
+        }
+        Ok(())
+    }
+}
+
+// `c = alpha * op(a) * op(b) + beta * c`, where `op(a)` is `m x k` and `op(b)` is `k x n`,
+// repeated for `batch` elements.
+pub struct Sgemm<'a> {
+    pub trans_a: bool,
+    pub trans_b: bool,
+    pub m: usize,
+    pub n: usize,
+    pub k: usize,
+    pub alpha: f32,
+    pub a: Operand<'a>,
+    pub b: Operand<'a>,
+    pub beta: f32,
+    pub c: Operand<'a>,
+    pub batch: usize,
+}
+
+impl<'a> Sgemm<'a> {
+    fn check(&self) -> Result<(), OpenCLError> {
+        let stored = |trans, rows, cols| if trans { (cols, rows) } else { (rows, cols) };
+        let (a_rows, a_cols) = stored(self.trans_a, self.m, self.k);
+        let (b_rows, b_cols) = stored(self.trans_b, self.k, self.n);
+        self.a.check('A', a_rows, a_cols, self.batch)?;
+        self.b.check('B', b_rows, b_cols, self.batch)?;
+        self.c.check('C', self.m, self.n, self.batch)
+    }
+}
+
+pub struct Gemm {
+    internal: Arc<OpenCLInternal>,
+    kernel: Lazy<Mutex<Kernel>>,
+}
+
+impl Gemm {
+    pub fn new(internal: &Arc<OpenCLInternal>) -> Gemm {
+        Gemm {
+            internal: Arc::clone(internal),
+            kernel: Lazy::new(internal, |internal| {
+                let program = internal.program("gemm")?;
+                let kernel = ocl_core::create_kernel(&program, "sgemm_kernel").map_err(|e| {
+                    OpenCLError::KernelCreation {
+                        kernel: "sgemm_kernel",
+                        error: e,
+                    }
+                })?;
+                Ok(Mutex::new(kernel))
+            }),
+        }
+    }
+
+    pub fn call(&self, p: &Sgemm) {
+        if let Err(e) = self.try_call(p) {
+            panic!("{}: {}", profiler::current_impl(), e);
+        }
+    }
+
+    pub fn try_call(&self, p: &Sgemm) -> Result<(), OpenCLError> {
+        // The native kernel does no bounds checking of its own. The operands are checked
+        // once for the whole call, which costs a few driver queries.
+        p.check()?;
+        if p.m == 0 || p.n == 0 || p.batch == 0 {
+            return Ok(());
+        }
+        if p.c.stride > 0 || p.batch == 1 {
+            return self.run(p);
+        }
+        // Every batch element accumulates into the same `c`, so they run one at a time.
+        for n in 0..p.batch {
+            self.run(&Sgemm {
+                a: p.a.offset(p.a.offset + n * p.a.stride),
+                b: p.b.offset(p.b.offset + n * p.b.stride),
+                batch: 1,
+                ..*p
+            })?;
+        }
+        Ok(())
+    }
+
+    fn run(&self, p: &Sgemm) -> Result<(), OpenCLError> {
+        #[cfg(any(feature = "clblast", feature = "clblast-dynamic"))]
+        {
+            if let Some(blas) = &self.internal.clblast {
+                return self.call_clblast(blas, p);
+            }
+        }
+        self.call_native(p)
+    }
+
+    #[cfg(any(feature = "clblast", feature = "clblast-dynamic"))]
+    fn call_clblast(&self, blas: &ClBlast, p: &Sgemm) -> Result<(), OpenCLError> {
+        let transpose = |t| {
+            if t {
+                clblast::transpose::YES
+            } else {
+                clblast::transpose::NO
+            }
+        };
+        let status = unsafe {
+            if p.batch == 1 {
+                self.internal.enqueue_blas(|queue, event| {
+                    (blas.sgemm)(
+                        clblast::layout::COL_MAJOR,
+                        transpose(p.trans_a),
+                        transpose(p.trans_b),
+                        p.m,
+                        p.n,
+                        p.k,
+                        p.alpha,
+                        p.a.buffer.as_ptr(),
+                        p.a.offset,
+                        p.a.ld,
+                        p.b.buffer.as_ptr(),
+                        p.b.offset,
+                        p.b.ld,
+                        p.beta,
+                        p.c.buffer.as_ptr(),
+                        p.c.offset,
+                        p.c.ld,
+                        queue,
+                        event,
+                    )
+                })
+            } else {
+                let offsets = |x: &Operand| {
+                    (0..p.batch)
+                        .map(|n| x.offset + n * x.stride)
+                        .collect::<Vec<_>>()
+                };
+                let alphas = vec![p.alpha; p.batch];
+                let betas = vec![p.beta; p.batch];
+                let a_offsets = offsets(&p.a);
+                let b_offsets = offsets(&p.b);
+                let c_offsets = offsets(&p.c);
+                self.internal.enqueue_blas(|queue, event| {
+                    (blas.sgemm_batched)(
+                        clblast::layout::COL_MAJOR,
+                        transpose(p.trans_a),
+                        transpose(p.trans_b),
+                        p.m,
+                        p.n,
+                        p.k,
+                        alphas.as_ptr(),
+                        p.a.buffer.as_ptr(),
+                        a_offsets.as_ptr(),
+                        p.a.ld,
+                        p.b.buffer.as_ptr(),
+                        b_offsets.as_ptr(),
+                        p.b.ld,
+                        betas.as_ptr(),
+                        p.c.buffer.as_ptr(),
+                        c_offsets.as_ptr(),
+                        p.c.ld,
+                        p.batch,
+                        queue,
+                        event,
+                    )
+                })
+            }
+        }
+        .map_err(OpenCLError::Queue)?;
+        ClBlastError::check(status).map_err(OpenCLError::ClBlast)
+    }
+
+    pub fn call_native(&self, p: &Sgemm) -> Result<(), OpenCLError> {
+        // An empty global work size is invalid before OpenCL 2.0.
+        if p.m == 0 || p.n == 0 || p.batch == 0 {
+            return Ok(());
+        }
+        let kernel = self.kernel.get()?;
+        let kernel = kernel.lock().unwrap();
+        let scalars = [
+            (0, p.trans_a as u32),
+            (1, p.trans_b as u32),
+            (2, p.m as u32),
+            (3, p.n as u32),
+            (4, p.k as u32),
+            (7, p.a.offset as u32),
+            (8, p.a.ld as u32),
+            (9, p.a.stride as u32),
+            (11, p.b.offset as u32),
+            (12, p.b.ld as u32),
+            (13, p.b.stride as u32),
+            (16, p.c.offset as u32),
+            (17, p.c.ld as u32),
+            (18, p.c.stride as u32),
+        ];
+        let g1 = (p.m + TILE - 1) / TILE;
+        let g2 = (p.n + TILE - 1) / TILE;
+        unsafe {
+            let set_arg = |index, value| {
+                ocl_core::set_kernel_arg(&kernel, index, value).map_err(|e| {
+                    OpenCLError::KernelArgument {
+                        kernel: "sgemm_kernel",
+                        index: index,
+                        error: e,
+                    }
+                })
+            };
+            for (index, value) in &scalars {
+                set_arg(*index, ArgVal::scalar(value))?;
+            }
+            set_arg(5, ArgVal::scalar(&p.alpha))?;
+            set_arg(6, ArgVal::mem(p.a.buffer))?;
+            set_arg(10, ArgVal::mem(p.b.buffer))?;
+            set_arg(14, ArgVal::scalar(&p.beta))?;
+            set_arg(15, ArgVal::mem(p.c.buffer))?;
+            let internal = &self.internal;
+            internal
+                .with_event(Command::Gemm, |wait_list, event| {
+                    ocl_core::enqueue_kernel(
+                        internal.queue(),
+                        &kernel,
+                        3,
+                        None,
+                        &[g1 * TILE, g2 * TILE, p.batch],
+                        Some([TILE, TILE, 1]),
+                        wait_list,
+                        event,
+                    )
+                })
+                .map_err(OpenCLError::Queue)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Gemm, GemmBackend, Operand, Sgemm};
+    use crate::transfer::tensor_buffer;
+    #[cfg(any(feature = "clblast", feature = "clblast-dynamic"))]
+    use crate::ClBlastError;
+    use crate::{DeviceSelector, OpenCL, OpenCLBuilder, OpenCLError};
+    use prima_undine::functions::BasicFunctions;
+    use prima_undine::shape;
+
+    fn reference(p: &Sgemm, a: &[f32], b: &[f32], c: &mut [f32]) {
+        for n in 0..p.batch {
+            for i in 0..p.m {
+                for j in 0..p.n {
+                    let mut sum = 0.;
+                    for k in 0..p.k {
+                        let a_ofs = p.a.offset + n * p.a.stride;
+                        let b_ofs = p.b.offset + n * p.b.stride;
+                        let x = if p.trans_a {
+                            a[a_ofs + k + i * p.a.ld]
+                        } else {
+                            a[a_ofs + i + k * p.a.ld]
+                        };
+                        let y = if p.trans_b {
+                            b[b_ofs + j + k * p.b.ld]
+                        } else {
+                            b[b_ofs + k + j * p.b.ld]
+                        };
+                        sum += x * y;
+                    }
+                    let z = &mut c[p.c.offset + n * p.c.stride + i + j * p.c.ld];
+                    *z = p.alpha * sum + p.beta * *z;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn check_native_sgemm() {
+        let dev = OpenCL::from_env().unwrap();
+        let cl = OpenCL::from_device(&dev).unwrap();
+        let gemm = Gemm::new(&cl.internal);
+        // Sizes that are not multiples of the tile size.
+        let (m, n, k, batch) = (19, 35, 21, 3);
+        let a_data = (0..m * k * batch)
+            .map(|x| (x % 13) as f32 - 6.)
+            .collect::<Vec<_>>();
+        let b_data = (0..k * n * batch)
+            .map(|x| (x % 7) as f32 - 3.)
+            .collect::<Vec<_>>();
+        let c_data = (0..m * n * batch)
+            .map(|x| (x % 5) as f32)
+            .collect::<Vec<_>>();
+        let a = dev.new_tensor_by_slice(shape![(m * k * batch) as u32], &a_data);
+        let b = dev.new_tensor_by_slice(shape![(k * n * batch) as u32], &b_data);
+        for &(trans_a, trans_b) in &[(false, false), (true, false), (false, true), (true, true)] {
+            for &b_stride in &[k * n, 0] {
+                let c = dev.new_tensor_by_slice(shape![(m * n * batch) as u32], &c_data);
+                let (a_ld, b_ld) = (if trans_a { k } else { m }, if trans_b { n } else { k });
+                unsafe {
+                    let p = Sgemm {
+                        trans_a: trans_a,
+                        trans_b: trans_b,
+                        m: m,
+                        n: n,
+                        k: k,
+                        alpha: 0.5,
+                        a: Operand::new(tensor_buffer(&a), a_ld, m * k),
+                        b: Operand::new(tensor_buffer(&b), b_ld, b_stride),
+                        beta: 2.,
+                        c: Operand::new(tensor_buffer(&c), m, m * n),
+                        batch: batch,
+                    };
+                    gemm.call_native(&p).unwrap();
+                    let mut expected = c_data.clone();
+                    reference(&p, &a_data, &b_data, &mut expected);
+                    assert_vector_ulps_eq!(expected, c.to_vec());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn check_gemm_backend() {
+        let dev = OpenCLBuilder::new()
+            .selector(DeviceSelector::from_env().unwrap())
+            .native_gemm(true)
+            .build()
+            .unwrap();
+        let cl = OpenCL::from_device(&dev).unwrap();
+        assert_eq!(GemmBackend::Native, cl.gemm_backend());
+        let a = dev.new_tensor_by_slice(shape![2, 2; 2], &[1., 2., 3., 4., 0., 1., 1., 0.]);
+        let b = dev.new_tensor_by_slice(shape![2, 2], &[1., 0., 1., 1.]);
+        let mut y = dev.new_tensor(shape![2, 2; 2]);
+        y.alloc();
+        dev.call_fw_impl("matmul_fw_impl", &[&a, &b], &[], &[], &mut [&mut y]);
+        assert_vector_ulps_eq!(vec![1., 2., 4., 6., 0., 1., 1., 1.], y.to_vec());
+
+        let default = OpenCL::from_device(crate::test_utils::get_device()).unwrap();
+        if cfg!(all(feature = "clblast", not(feature = "clblast-dynamic"))) {
+            assert_eq!(GemmBackend::ClBlast, default.gemm_backend());
+        } else if !cfg!(feature = "clblast-dynamic") {
+            assert_eq!(GemmBackend::Native, default.gemm_backend());
+        }
+    }
+
+    #[cfg(feature = "clblast-dynamic")]
+    #[test]
+    fn check_missing_clblast_library() {
+        let dev = OpenCLBuilder::new()
+            .selector(DeviceSelector::from_env().unwrap())
+            .clblast_library("/nonexistent/libclblast.so")
+            .build()
+            .unwrap();
+        let cl = OpenCL::from_device(&dev).unwrap();
+        assert_eq!(GemmBackend::Native, cl.gemm_backend());
+    }
+
+    #[test]
+    fn check_gemm_errors() {
+        let dev = OpenCL::from_env().unwrap();
+        let cl = OpenCL::from_device(&dev).unwrap();
+        let gemm = Gemm::new(&cl.internal);
+        let a = dev.new_tensor_by_constant(shape![8, 8], 1.);
+        let c = dev.new_tensor_by_constant(shape![8, 8], 0.);
+        let call = |m, lda, batch| unsafe {
+            gemm.try_call(&Sgemm {
+                trans_a: false,
+                trans_b: false,
+                m: m,
+                n: m,
+                k: m,
+                alpha: 1.,
+                a: Operand::new(tensor_buffer(&a), lda, m * m),
+                b: Operand::new(tensor_buffer(&a), m, m * m),
+                beta: 0.,
+                c: Operand::new(tensor_buffer(&c), m, m * m),
+                batch: batch,
+            })
+        };
+        match call(8, 4, 1) {
+            Err(OpenCLError::InvalidLeadDim { matrix, ld, rows }) => {
+                assert_eq!(('A', 4, 8), (matrix, ld, rows))
+            }
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("gemm must fail"),
+        }
+        match call(100, 100, 1) {
+            Err(OpenCLError::InsufficientMemory {
+                matrix, required, ..
+            }) => assert_eq!(('A', 40000), (matrix, required)),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("gemm must fail"),
+        }
+        // The second batch element runs past the end of every buffer.
+        match call(8, 8, 2) {
+            Err(OpenCLError::InsufficientMemory {
+                matrix, required, ..
+            }) => assert_eq!(('A', 512), (matrix, required)),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("gemm must fail"),
+        }
+        call(8, 8, 1).unwrap();
+        assert_vector_ulps_eq!(vec![8.; 64], c.to_vec());
+        // Nothing is enqueued for an empty product.
+        call(0, 1, 1).unwrap();
+        gemm.call_native(&Sgemm {
+            trans_a: false,
+            trans_b: false,
+            m: 0,
+            n: 8,
+            k: 8,
+            alpha: 1.,
+            a: unsafe { Operand::new(tensor_buffer(&a), 1, 0) },
+            b: unsafe { Operand::new(tensor_buffer(&a), 8, 0) },
+            beta: 0.,
+            c: unsafe { Operand::new(tensor_buffer(&c), 1, 0) },
+            batch: 1,
+        })
+        .unwrap();
+    }
+
+    // Bypasses `Sgemm::check` so that CLBlast's own argument validation is exercised.
+    #[cfg(any(feature = "clblast", feature = "clblast-dynamic"))]
+    #[test]
+    fn check_clblast_errors() {
+        let dev = OpenCL::from_env().unwrap();
+        let cl = OpenCL::from_device(&dev).unwrap();
+        let blas = match &cl.internal.clblast {
+            Some(blas) => blas,
+            None => return,
+        };
+        let gemm = Gemm::new(&cl.internal);
+        let a = dev.new_tensor_by_constant(shape![8, 8], 1.);
+        let c = dev.new_tensor_by_constant(shape![8, 8], 0.);
+        let call = |lda, batch| unsafe {
+            gemm.call_clblast(
+                blas,
+                &Sgemm {
+                    trans_a: false,
+                    trans_b: false,
+                    m: 8,
+                    n: 8,
+                    k: 8,
+                    alpha: 1.,
+                    a: Operand::new(tensor_buffer(&a), lda, 0),
+                    b: Operand::new(tensor_buffer(&a), 8, 0),
+                    beta: 0.,
+                    c: Operand::new(tensor_buffer(&c), 8, 0),
+                    batch: batch,
+                },
+            )
+        };
+        match call(4, 1) {
+            Err(OpenCLError::ClBlast(e)) => assert_eq!(ClBlastError::InvalidLeadDimA, e),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("gemm must fail"),
+        }
+        match call(8, 0) {
+            Err(OpenCLError::ClBlast(e)) => assert_eq!(ClBlastError::InvalidBatchCount, e),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("gemm must fail"),
+        }
+        call(8, 1).unwrap();
+        assert_vector_ulps_eq!(vec![8.; 64], c.to_vec());
+    }
+}
@@ -15,12 +15,13 @@
 kernel __attribute__((reqd_work_group_size(GROUP_SIZE, 1, 1))) \
 void argmin_kernel_##GROUP_SIZE( \
     const global float *px, const unsigned skip, \
-    const unsigned n, global unsigned *py) { \
+    const unsigned n, const unsigned first, global unsigned *py) { \
   const unsigned bid = get_group_id(0); \
+  const unsigned pos = first + bid; \
   const unsigned tid = get_local_id(0); \
   local float min_val[GROUP_SIZE]; \
   local unsigned argmin_val[GROUP_SIZE]; \
-  px += bid % skip + (bid / skip) * skip * n; \
+  px += pos % skip + (pos / skip) * skip * n; \
   min_val[tid] = INFINITY; \
   for (unsigned i = tid; i < n; i += GROUP_SIZE) { \
     const float val = px[i * skip]; \
@@ -1,15 +1,15 @@
 kernel __attribute__((reqd_work_group_size(256, 1, 1)))
 void init_argsort_kernel(
-    const unsigned size, global unsigned *py) {
+    const unsigned size, const unsigned first, global unsigned *py) {
   const unsigned gid = get_global_id(0);
-  if (gid < size) py[gid] = gid;
+  if (gid < size) py[gid] = first + gid;
 }
 
 kernel __attribute__((reqd_work_group_size(256, 1, 1)))
 void argsort_kernel(
     const global float *px, const unsigned block_size, const unsigned dist,
     const unsigned skip, const unsigned len, const unsigned idx_len, const unsigned size,
-    const unsigned idx_size, global unsigned *py) {
+    const unsigned idx_size, const unsigned first, global unsigned *py) {
   const unsigned gid = get_global_id(0);
   const unsigned i = gid % skip;
   const unsigned j = gid / skip;
@@ -26,7 +26,8 @@ void argsort_kernel(
     const unsigned q = i + (d2 < len ? inner_trans : outer_trans) + d2 * skip;
     const unsigned y1 = py[p];
     const unsigned y2 = py[q];
-    if (block_idx % 2 == 0 ? (y1 >= size || (y2 < size && px[y1] > px[y2])) : (y2 >= size || (y1 < size && px[y2] > px[y1]))) {
+    const unsigned end = first + size;
+    if (block_idx % 2 == 0 ? (y1 >= end || (y2 < end && px[y1] > px[y2])) : (y2 >= end || (y1 < end && px[y2] > px[y1]))) {
       py[p] = y2;
       py[q] = y1;
     }
@@ -0,0 +1,45 @@
+#define GEMM_TILE 16
+
+// Column-major C = alpha * op(A) * op(B) + beta * C for each batch element.
+kernel __attribute__((reqd_work_group_size(GEMM_TILE, GEMM_TILE, 1)))
+void sgemm_kernel(
+    const unsigned trans_a, const unsigned trans_b,
+    const unsigned m, const unsigned n, const unsigned k, const float alpha,
+    const global float *pa, const unsigned a_offset, const unsigned lda,
+    const unsigned a_stride,
+    const global float *pb, const unsigned b_offset, const unsigned ldb,
+    const unsigned b_stride,
+    const float beta,
+    global float *pc, const unsigned c_offset, const unsigned ldc,
+    const unsigned c_stride) {
+  const unsigned li = get_local_id(0);
+  const unsigned lj = get_local_id(1);
+  const unsigned i = get_global_id(0);
+  const unsigned j = get_global_id(1);
+  const unsigned bid_z = get_group_id(2);
+  local float a_tile[GEMM_TILE][GEMM_TILE + 1];
+  local float b_tile[GEMM_TILE][GEMM_TILE + 1];
+  pa += a_offset + bid_z * a_stride;
+  pb += b_offset + bid_z * b_stride;
+  pc += c_offset + bid_z * c_stride;
+  float sum = 0;
+  for (unsigned t = 0; t < k; t += GEMM_TILE) {
+    const unsigned ak = t + lj;
+    const unsigned bk = t + li;
+    a_tile[lj][li] = i < m && ak < k
+      ? (trans_a ? pa[ak + i * lda] : pa[i + ak * lda]) : 0;
+    b_tile[lj][li] = j < n && bk < k
+      ? (trans_b ? pb[j + bk * ldb] : pb[bk + j * ldb]) : 0;
+    barrier(CLK_LOCAL_MEM_FENCE);
+    for (unsigned kk = 0; kk < GEMM_TILE; ++kk) {
+      sum += a_tile[kk][li] * b_tile[lj][kk];
+    }
+    barrier(CLK_LOCAL_MEM_FENCE);
+  }
+  if (i < m && j < n) {
+    global float *py = pc + i + j * ldc;
+    *py = beta == 0 ? alpha * sum : alpha * sum + beta * *py;
+  }
+}
+
+#undef GEMM_TILE
@@ -0,0 +1,137 @@
+use std::sync::{Arc, Mutex};
+
+use prima_undine::device_impl::{
+    FunctionBwImpl, FunctionFwF32Impl, FunctionFwImpl, FunctionFwU32Impl,
+};
+use prima_undine::Tensor;
+
+use crate::profiler;
+use crate::{OpenCLError, OpenCLInternal};
+
+type Initializer<T> = dyn Fn(&Arc<OpenCLInternal>) -> Result<T, OpenCLError> + Send + Sync;
+
+pub struct Lazy<T> {
+    init: Box<Initializer<T>>,
+    value: Mutex<Option<Arc<T>>>,
+    internal: Arc<OpenCLInternal>,
+}
+
+impl<T> Lazy<T> {
+    pub fn new<F>(internal: &Arc<OpenCLInternal>, init: F) -> Lazy<T>
+    where
+        F: Fn(&Arc<OpenCLInternal>) -> Result<T, OpenCLError> + Send + Sync + 'static,
+    {
+        Lazy {
+            init: Box::new(init),
+            value: Mutex::new(None),
+            internal: Arc::clone(internal),
+        }
+    }
+
+    pub fn get(&self) -> Result<Arc<T>, OpenCLError> {
+        let mut value = self.value.lock().unwrap();
+        if value.is_none() {
+            *value = Some(Arc::new((self.init)(&self.internal)?));
+        }
+        Ok(Arc::clone(value.as_ref().unwrap()))
+    }
+}
+
+pub struct LazyImpl<T> {
+    name: &'static str,
+    inner: Lazy<T>,
+}
+
+impl<T> LazyImpl<T> {
+    pub fn new<F>(internal: &Arc<OpenCLInternal>, name: &'static str, init: F) -> LazyImpl<T>
+    where
+        F: Fn(&Arc<OpenCLInternal>) -> Result<T, OpenCLError> + Send + Sync + 'static,
+    {
+        LazyImpl {
+            name: name,
+            inner: Lazy::new(internal, init),
+        }
+    }
+
+    fn keys(&self, tensors: &[&Tensor]) -> Vec<usize> {
+        self.inner.internal.mem_keys(tensors)
+    }
+
+    fn with<R, F: FnOnce(&T) -> R>(&self, reads: Vec<usize>, writes: Vec<usize>, f: F) -> R {
+        let internal = &self.inner.internal;
+        profiler::with_current_impl(self.name, || {
+            internal.with_access(reads, writes, || match self.inner.get() {
+                Ok(value) => f(&value),
+                Err(e) => panic!("{}: {}", self.name, e),
+            })
+        })
+    }
+}
+
+impl<T: FunctionFwImpl> FunctionFwImpl for LazyImpl<T> {
+    fn call(&self, xs: &[&Tensor], u32data: &[u32], f32data: &[f32], ys: &mut [&mut Tensor]) {
+        let writes = self.keys(&ys.iter().map(|y| &**y).collect::<Vec<_>>());
+        self.with(self.keys(xs), writes, |f| f.call(xs, u32data, f32data, ys));
+    }
+}
+
+impl<T: FunctionBwImpl> FunctionBwImpl for LazyImpl<T> {
+    fn call(
+        &self,
+        xs: &[&Tensor],
+        ys: &[&Tensor],
+        gys: &[&Tensor],
+        u32data: &[u32],
+        f32data: &[f32],
+        gx: &mut Tensor,
+    ) {
+        let reads = self.keys(&[xs, ys, gys].concat());
+        let writes = self.keys(&[&*gx]);
+        self.with(reads, writes, |f| f.call(xs, ys, gys, u32data, f32data, gx));
+    }
+}
+
+impl<T: FunctionFwF32Impl> FunctionFwF32Impl for LazyImpl<T> {
+    fn call(&self, xs: &[&Tensor], u32data: &[u32], f32data: &[f32], ys: &mut [f32]) {
+        self.with(self.keys(xs), vec![], |f| f.call(xs, u32data, f32data, ys));
+    }
+}
+
+impl<T: FunctionFwU32Impl> FunctionFwU32Impl for LazyImpl<T> {
+    fn call(&self, xs: &[&Tensor], u32data: &[u32], f32data: &[f32], ys: &mut [u32]) {
+        self.with(self.keys(xs), vec![], |f| f.call(xs, u32data, f32data, ys));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::get_device;
+    use crate::{OpenCL, PROGRAM_NAMES};
+    use prima_undine::functions::BasicFunctions;
+    use prima_undine::shape;
+    use prima_undine::DeviceImpl;
+
+    #[test]
+    fn check_lazy_compilation() {
+        let dev = OpenCL::from_env().unwrap();
+        let cl = OpenCL::from_device(&dev).unwrap();
+        assert_eq!(0, cl.internal.num_compiled_programs());
+        let x = dev.new_tensor_by_slice(shape![2, 2], &[1., -2., 3., -4.]);
+        let mut y = dev.new_tensor(shape![2, 2]);
+        y.alloc();
+        dev.call_fw_impl("neg_fw_impl", &[&x], &[], &[], &mut [&mut y]);
+        assert_vector_ulps_eq!(vec![-1., 2., -3., 4.], y.to_vec());
+        assert_eq!(1, cl.internal.num_compiled_programs());
+        dev.call_fw_impl("neg_fw_impl", &[&x], &[], &[], &mut [&mut y]);
+        assert_eq!(1, cl.internal.num_compiled_programs());
+        cl.warm_up().unwrap();
+        assert_eq!(PROGRAM_NAMES.len(), cl.internal.num_compiled_programs());
+    }
+
+    #[test]
+    fn check_from_device() {
+        let dev = get_device();
+        let cl = OpenCL::from_device(dev).unwrap();
+        assert_eq!(dev.identifier(), cl.identifier());
+    }
+}
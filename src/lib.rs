@@ -2,17 +2,63 @@
 #[macro_use]
 mod test_utils;
 
+mod allocator;
+mod builder;
+mod cache;
+#[cfg(any(feature = "clblast", feature = "clblast-dynamic"))]
 mod clblast;
+mod dependency;
+mod error;
+mod fence;
+mod gemm;
+mod lazy;
 mod ops;
-
+mod profiler;
+mod properties;
+mod selector;
+mod stream;
+mod tracer;
+mod transfer;
+mod view;
+
+use std::collections::HashMap;
 use std::ffi::{c_void, CString};
+#[cfg(feature = "clblast-dynamic")]
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicPtr, Ordering};
-use std::sync::{Arc, Mutex};
-
-use ocl_core::types::abs::{CommandQueue, Context, Mem, Program};
-use ocl_core::ContextProperties;
-
-use prima_undine::{Device, DeviceImpl};
+use std::sync::{Arc, Mutex, Weak};
+
+use lazy_static::lazy_static;
+
+use ocl_core::types::abs::{CommandQueue, Context, Event, Kernel, Mem, Program};
+use ocl_core::{CommandQueueInfo, CommandQueueInfoResult, CommandQueueProperties};
+use ocl_core::{ContextProperties, DeviceId, DeviceInfo, DeviceInfoResult};
+use ocl_core::{MemInfo, MemInfoResult};
+use ocl_core::{ProgramBuildInfo, ProgramBuildInfoResult};
+use ocl_core::{ProgramInfo, ProgramInfoResult};
+
+use prima_undine::{Device, DeviceImpl, Shape, Tensor};
+
+use crate::allocator::MemoryPool;
+use crate::cache::ProgramCache;
+#[cfg(any(feature = "clblast", feature = "clblast-dynamic"))]
+use crate::clblast::ClBlast;
+use crate::dependency::DependencyTracker;
+use crate::profiler::Profiler;
+use crate::tracer::{Command, Tracer};
+use crate::transfer::{StagingPool, Uploader};
+
+pub use crate::allocator::MemoryStats;
+pub use crate::builder::OpenCLBuilder;
+pub use crate::error::{ClBlastError, OpenCLError};
+pub use crate::fence::Fence;
+pub use crate::gemm::GemmBackend;
+pub use crate::profiler::{ProfileEntry, ProfileReport};
+pub use crate::properties::{DeviceKind, DeviceProperties, PlatformProperties};
+pub use crate::selector::DeviceSelector;
+pub use crate::tracer::{Trace, TraceEvent};
+pub use crate::transfer::PendingRead;
+pub use crate::view::TensorView;
 
 macro_rules! kernel_string {
     ( $kernel_name:ident ) => {
@@ -22,680 +68,1686 @@ macro_rules! kernel_string {
             stringify!($kernel_name),
             ".in"
         )))
-        .unwrap();
+        .unwrap()
+    };
+}
+
+macro_rules! program_sources {
+    ( [ $( $standalone:ident ),* ], [ $( $with_common:ident ),* ] ) => {
+        const PROGRAM_NAMES: &[&str] = &[
+            $( stringify!($standalone), )*
+            $( stringify!($with_common), )*
+        ];
+
+        fn program_source(name: &str) -> (String, String) {
+            match name {
+                $(
+                    stringify!($standalone) => (
+                        stringify!($standalone).to_string(),
+                        kernel_string!($standalone),
+                    ),
+                )*
+                $(
+                    stringify!($with_common) => (
+                        concat!("common + ", stringify!($with_common)).to_string(),
+                        kernel_string!(common) + &kernel_string!($with_common),
+                    ),
+                )*
+                _ => panic!("unknown program: {}", name),
+            }
+        }
+    };
+}
+
+program_sources!(
+    [identity, xorshift, gemm],
+    [
+        add_assign,
+        sub_assign,
+        mul_assign,
+        argmax,
+        argmin,
+        argsort,
+        neg,
+        add,
+        sub,
+        mul,
+        div,
+        powf,
+        sqrt,
+        abs,
+        powi,
+        sin,
+        cos,
+        tan,
+        exp,
+        ln,
+        tanh,
+        sigmoid,
+        softplus,
+        sum,
+        logsumexp,
+        max,
+        min,
+        broadcast,
+        transpose,
+        permute_dims,
+        flip,
+        triangular_l,
+        triangular_u,
+        prelu,
+        elu,
+        slice,
+        pick,
+        concat,
+        batch_concat,
+        batch_pick,
+        batch_slice,
+        batch_sum
+    ]
+);
+
+macro_rules! register_impl {
+    (
+        $dev:ident . $register:ident,
+        $name:expr,
+        $internal:ident,
+        $program:ident, $shared:ident => $ty:ty
+    ) => {{
+        let shared = Arc::clone(&$shared);
+        $dev.$register(
+            $name,
+            lazy::LazyImpl::new(&$internal, $name, move |internal| {
+                Ok(<$ty>::new(
+                    &shared.get()?,
+                    &internal.program(stringify!($program))?,
+                    internal,
+                ))
+            }),
+        )
+    }};
+    ( $dev:ident . $register:ident, $name:expr, $internal:ident, $program:ident => $ty:ty ) => {
+        $dev.$register(
+            $name,
+            lazy::LazyImpl::new(&$internal, $name, |internal| {
+                Ok(<$ty>::new(
+                    &internal.program(stringify!($program))?,
+                    internal,
+                ))
+            }),
+        )
+    };
+    ( $dev:ident . $register:ident, $name:expr, $internal:ident, $ty:ty ) => {
+        $dev.$register(
+            $name,
+            lazy::LazyImpl::new(&$internal, $name, |internal| Ok(<$ty>::new(internal))),
+        )
     };
 }
 
 pub struct OpenCLInternal {
     context: Context,
     queue: CommandQueue,
+    streams: Vec<CommandQueue>,
+    properties: DeviceProperties,
+    zero_copy: bool,
+    pool: MemoryPool,
+    staging: StagingPool,
+    uploader: Mutex<Uploader>,
+    build_options: CString,
+    programs: Mutex<HashMap<&'static str, Program>>,
+    program_cache: Option<ProgramCache>,
+    profiler: Option<Profiler>,
+    tracer: Option<Tracer>,
+    dependencies: Option<Arc<DependencyTracker>>,
+    #[cfg(any(feature = "clblast", feature = "clblast-dynamic"))]
+    clblast: Option<ClBlast>,
 }
 
 impl OpenCLInternal {
-    fn new(platform_id: usize, device_id: usize) -> OpenCLInternal {
-        let platforms = ocl_core::get_platform_ids().unwrap();
-        let platform = platforms[platform_id];
-        let devices = ocl_core::get_device_ids(&platform, None, None).unwrap();
-        let device = devices[device_id];
-        let context_properties = ContextProperties::new().platform(platform);
+    fn new(
+        platform_id: usize,
+        device_id: usize,
+        builder: &OpenCLBuilder,
+    ) -> Result<OpenCLInternal, OpenCLError> {
+        Ok(Self::new_shared(platform_id, &[device_id], builder)?
+            .pop()
+            .unwrap())
+    }
 
+    // One instance per entry of `device_ids`, all sharing a single context.
+    fn new_shared(
+        platform_id: usize,
+        device_ids: &[usize],
+        builder: &OpenCLBuilder,
+    ) -> Result<Vec<OpenCLInternal>, OpenCLError> {
+        let platforms = ocl_core::get_platform_ids().map_err(OpenCLError::PlatformQuery)?;
+        if platforms.is_empty() {
+            return Err(OpenCLError::NoPlatform);
+        }
+        let platform = *platforms
+            .get(platform_id)
+            .ok_or(OpenCLError::PlatformOutOfRange {
+                index: platform_id,
+                count: platforms.len(),
+            })?;
+        let devices =
+            ocl_core::get_device_ids(&platform, None, None).map_err(OpenCLError::PlatformQuery)?;
+        let mut context_devices = vec![];
+        for &device_id in device_ids {
+            let device = *devices
+                .get(device_id)
+                .ok_or(OpenCLError::DeviceOutOfRange {
+                    index: device_id,
+                    count: devices.len(),
+                })?;
+            if !context_devices.contains(&device) {
+                context_devices.push(device);
+            }
+        }
+        let context_properties = ContextProperties::new().platform(platform);
         let context =
-            ocl_core::create_context(Some(&context_properties), &[device], None, None).unwrap();
-        let queue = ocl_core::create_command_queue(&context, &device, None).unwrap();
+            ocl_core::create_context(Some(&context_properties), &context_devices, None, None)
+                .map_err(OpenCLError::ContextCreation)?;
+        device_ids
+            .iter()
+            .map(|&device_id| {
+                Self::with_context(
+                    &context,
+                    None,
+                    platform_id,
+                    device_id,
+                    devices[device_id],
+                    builder,
+                )
+            })
+            .collect()
+    }
+
+    // Uses a context and queue created elsewhere. The queue's own properties are kept, so
+    // profiling and tracing require it to be created with `QUEUE_PROFILING_ENABLE`.
+    fn from_queue(
+        context: &Context,
+        queue: &CommandQueue,
+        builder: &OpenCLBuilder,
+    ) -> Result<OpenCLInternal, OpenCLError> {
+        let device = queue.device().map_err(OpenCLError::PlatformQuery)?;
+        let platform = match ocl_core::get_device_info(&device, DeviceInfo::Platform)
+            .map_err(OpenCLError::PlatformQuery)?
+        {
+            DeviceInfoResult::Platform(platform) => platform,
+            _ => panic!(),
+        };
+        let platforms = ocl_core::get_platform_ids().map_err(OpenCLError::PlatformQuery)?;
+        let platform_id = platforms
+            .iter()
+            .position(|p| *p == platform)
+            .ok_or(OpenCLError::UnknownDevice)?;
+        let devices =
+            ocl_core::get_device_ids(&platform, None, None).map_err(OpenCLError::PlatformQuery)?;
+        let device_id = devices
+            .iter()
+            .position(|d| *d == device)
+            .ok_or(OpenCLError::UnknownDevice)?;
+        Self::with_context(
+            context,
+            Some(queue),
+            platform_id,
+            device_id,
+            device,
+            builder,
+        )
+    }
+
+    fn with_context(
+        context: &Context,
+        queue: Option<&CommandQueue>,
+        platform_id: usize,
+        device_id: usize,
+        device: DeviceId,
+        builder: &OpenCLBuilder,
+    ) -> Result<OpenCLInternal, OpenCLError> {
+        let properties = DeviceProperties::query(platform_id, device_id, &device)?;
+        let context = context.clone();
+        let (queue, queue_properties) = match queue {
+            Some(queue) => {
+                let queue_properties =
+                    match ocl_core::get_command_queue_info(queue, CommandQueueInfo::Properties)
+                        .map_err(OpenCLError::QueueCreation)?
+                    {
+                        CommandQueueInfoResult::Properties(p) => p,
+                        _ => panic!(),
+                    };
+                (queue.clone(), queue_properties)
+            }
+            None => {
+                let mut queue_properties = builder
+                    .queue_properties
+                    .unwrap_or(CommandQueueProperties::empty());
+                if builder.profiling || builder.tracing {
+                    queue_properties |= ocl_core::QUEUE_PROFILING_ENABLE;
+                }
+                if builder.out_of_order
+                    && properties
+                        .queue_properties
+                        .contains(ocl_core::QUEUE_OUT_OF_ORDER_EXEC_MODE_ENABLE)
+                {
+                    queue_properties |= ocl_core::QUEUE_OUT_OF_ORDER_EXEC_MODE_ENABLE;
+                }
+                let queue =
+                    ocl_core::create_command_queue(&context, &device, Some(queue_properties))
+                        .map_err(OpenCLError::QueueCreation)?;
+                (queue, queue_properties)
+            }
+        };
+        let out_of_order = queue_properties.contains(ocl_core::QUEUE_OUT_OF_ORDER_EXEC_MODE_ENABLE);
+        let streams = (0..builder.streams)
+            .map(|_| ocl_core::create_command_queue(&context, &device, Some(queue_properties)))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(OpenCLError::QueueCreation)?;
+
+        let build_options = builder.build_options_string();
+        let program_cache = match &builder.program_cache {
+            Some(dir) => Some(ProgramCache::new(dir, &device, &build_options)?),
+            None => None,
+        };
+
+        let zero_copy = builder.zero_copy && properties.host_unified_memory;
+        let mem_flags = if zero_copy {
+            ocl_core::MEM_READ_WRITE | ocl_core::MEM_ALLOC_HOST_PTR
+        } else {
+            ocl_core::MEM_READ_WRITE
+        };
+        let dependencies = if out_of_order {
+            Some(Arc::new(DependencyTracker::new()))
+        } else {
+            None
+        };
+        let pool = MemoryPool::new(
+            &context,
+            mem_flags,
+            builder.memory_pool_cap,
+            builder.memory_limit,
+            dependencies.as_ref().map(|dependencies| {
+                let dependencies = Arc::clone(dependencies);
+                Box::new(move |buffer: &Mem| dependencies.forget(buffer.as_ptr() as usize))
+                    as Box<allocator::ReleaseHook>
+            }),
+        );
+        let staging = StagingPool::new(&context);
+        let uploader = Mutex::new(Uploader::new(
+            &context,
+            std::iter::once(&queue).chain(streams.iter()),
+        ));
+
+        #[cfg(all(feature = "clblast", not(feature = "clblast-dynamic")))]
+        let clblast = if builder.native_gemm {
+            None
+        } else {
+            Some(ClBlast::linked())
+        };
+        // A missing or incompatible library falls back to the native kernel.
+        #[cfg(feature = "clblast-dynamic")]
+        let clblast = if builder.native_gemm {
+            None
+        } else {
+            let library = builder
+                .clblast_library
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(ClBlast::default_library()));
+            ClBlast::load(&library).ok()
+        };
 
-        OpenCLInternal {
+        Ok(OpenCLInternal {
             context: context,
             queue: queue,
+            streams: streams,
+            properties: properties,
+            zero_copy: zero_copy,
+            pool: pool,
+            staging: staging,
+            uploader: uploader,
+            build_options: CString::new(build_options).unwrap(),
+            programs: Mutex::new(HashMap::new()),
+            program_cache: program_cache,
+            profiler: if builder.profiling {
+                Some(Profiler::new())
+            } else {
+                None
+            },
+            tracer: if builder.tracing {
+                Some(Tracer::new())
+            } else {
+                None
+            },
+            dependencies: dependencies,
+            #[cfg(any(feature = "clblast", feature = "clblast-dynamic"))]
+            clblast: clblast,
+        })
+    }
+
+    fn queue(&self) -> &CommandQueue {
+        match stream::current_stream(self.owner()) {
+            0 => &self.queue,
+            stream => &self.streams[stream - 1],
         }
     }
 
-    fn build_program(&self, src: &str) -> Program {
-        let src_cstring = CString::new(src).unwrap();
-        let program = ocl_core::create_program_with_source(&self.context, &[src_cstring]).unwrap();
-        ocl_core::build_program(
-            &program,
-            None::<&[()]>,
-            &CString::new("").unwrap(),
-            None,
-            None,
+    fn queues(&self) -> impl Iterator<Item = &CommandQueue> {
+        std::iter::once(&self.queue).chain(self.streams.iter())
+    }
+
+    // Markers on every stream, so that a released buffer is reused only after all streams
+    // have finished with it. This runs while a tensor is dropped, so a queue that fails to
+    // take a marker is drained instead of panicking.
+    fn release_events(&self) -> Vec<Event> {
+        if self.streams.is_empty() {
+            return vec![];
+        }
+        self.queues()
+            .filter_map(|queue| {
+                let mut event = Event::null();
+                let result = unsafe {
+                    ocl_core::enqueue_marker_with_wait_list(
+                        queue,
+                        None::<Event>,
+                        Some(&mut event),
+                        None,
+                    )
+                };
+                match result {
+                    Ok(()) => Some(event),
+                    Err(e) => {
+                        eprintln!(
+                            "prima_undine_opencl: failed to enqueue a release marker: {}",
+                            e
+                        );
+                        let _ = ocl_core::finish(queue);
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    // Allocates a buffer that is ready for use once the commands still using it, if it was
+    // cached, have completed.
+    fn new_handle(&self, size: usize) -> Result<AtomicPtr<c_void>, OpenCLError> {
+        if let Some(handle) = allocator::take_allocated_handle() {
+            return Ok(handle);
+        }
+        let (handle, release_events) = self.pool.new_handle(size)?;
+        if !release_events.is_empty() {
+            let result = unsafe {
+                ocl_core::enqueue_barrier_with_wait_list(
+                    self.queue(),
+                    Some(&release_events[..]),
+                    None::<&mut Event>,
+                    None,
+                )
+            };
+            if let Err(e) = result {
+                self.pool.drop_handle(&handle, release_events);
+                return Err(OpenCLError::Queue(e));
+            }
+        }
+        Ok(handle)
+    }
+
+    fn identifier(&self) -> String {
+        format!(
+            "OpenCL,{},{}",
+            self.context.as_ptr() as usize,
+            self.queue.as_ptr() as usize
         )
-        .unwrap();
-        program
     }
+
+    fn find(identifier: &str) -> Option<Arc<OpenCLInternal>> {
+        let mut instances = INSTANCES.lock().unwrap();
+        instances.retain(|instance| instance.upgrade().is_some());
+        instances
+            .iter()
+            .filter_map(|instance| instance.upgrade())
+            .find(|internal| internal.identifier() == identifier)
+    }
+
+    fn shares_context(&self, other: &OpenCLInternal) -> bool {
+        self.context.as_ptr() == other.context.as_ptr()
+    }
+
+    fn owner(&self) -> usize {
+        self as *const OpenCLInternal as usize
+    }
+
+    // The `cl_mem` addresses of this device's tensors, used as dependency keys.
+    fn mem_keys(&self, tensors: &[&Tensor]) -> Vec<usize> {
+        if self.dependencies.is_none() {
+            return vec![];
+        }
+        let identifier = self.identifier();
+        tensors
+            .iter()
+            .filter(|tensor| tensor.device().identifier() == identifier)
+            .filter_map(|tensor| unsafe {
+                (tensor.handle().load(Ordering::Acquire) as *const Mem).as_ref()
+            })
+            .map(|mem| mem.as_ptr() as usize)
+            .collect()
+    }
+
+    fn with_access<R, F: FnOnce() -> R>(&self, reads: Vec<usize>, writes: Vec<usize>, f: F) -> R {
+        match &self.dependencies {
+            Some(dependencies) => dependencies.with_access(self.owner(), reads, writes, f),
+            None => f(),
+        }
+    }
+
+    fn wait_list(&self) -> Result<Vec<Event>, ocl_core::Error> {
+        let dependencies = match &self.dependencies {
+            Some(dependencies) => dependencies,
+            None => return Ok(vec![]),
+        };
+        match dependencies.wait_list(self.owner()) {
+            Some(wait_list) => Ok(wait_list),
+            None => {
+                // Untracked commands wait for everything enqueued before them.
+                unsafe {
+                    ocl_core::enqueue_barrier_with_wait_list(
+                        self.queue(),
+                        None::<Event>,
+                        None::<&mut Event>,
+                        None,
+                    )?;
+                }
+                Ok(vec![])
+            }
+        }
+    }
+
+    fn gemm_backend(&self) -> GemmBackend {
+        #[cfg(any(feature = "clblast", feature = "clblast-dynamic"))]
+        {
+            if self.clblast.is_some() {
+                return GemmBackend::ClBlast;
+            }
+        }
+        GemmBackend::Native
+    }
+
+    fn records_events(&self) -> bool {
+        self.profiler.is_some() || self.tracer.is_some() || self.dependencies.is_some()
+    }
+
+    fn record_event(&self, command: Command, event: Event) {
+        if let Some(dependencies) = &self.dependencies {
+            dependencies.record(self.owner(), &event);
+        }
+        if let Some(tracer) = &self.tracer {
+            tracer.record(command, event.clone());
+        }
+        if let Some(profiler) = &self.profiler {
+            if command.is_compute() {
+                profiler.record(event);
+            }
+        }
+    }
+
+    unsafe fn enqueue_with_event<T, F>(
+        &self,
+        command: Command,
+        f: F,
+    ) -> Result<(T, Event), ocl_core::Error>
+    where
+        F: FnOnce(Option<&[Event]>, Option<&mut Event>) -> Result<T, ocl_core::Error>,
+    {
+        let wait_list = self.wait_list()?;
+        let wait_list = if wait_list.is_empty() {
+            None
+        } else {
+            Some(&wait_list[..])
+        };
+        let mut event = Event::null();
+        let ret = f(wait_list, Some(&mut event))?;
+        self.record_event(command, event.clone());
+        Ok((ret, event))
+    }
+
+    unsafe fn with_event<T, F>(&self, command: Command, f: F) -> Result<T, ocl_core::Error>
+    where
+        F: FnOnce(Option<&[Event]>, Option<&mut Event>) -> Result<T, ocl_core::Error>,
+    {
+        if !self.records_events() {
+            return f(None, None);
+        }
+        self.enqueue_with_event(command, f).map(|(ret, _)| ret)
+    }
+
+    unsafe fn enqueue_kernel(
+        &self,
+        kernel: &Kernel,
+        work_dims: u32,
+        global_work_offset: Option<[usize; 3]>,
+        global_work_dims: &[usize; 3],
+        local_work_dims: Option<[usize; 3]>,
+    ) -> Result<(), ocl_core::Error> {
+        self.with_event(Command::Kernel, |wait_list, event| {
+            ocl_core::enqueue_kernel(
+                self.queue(),
+                kernel,
+                work_dims,
+                global_work_offset,
+                global_work_dims,
+                local_work_dims,
+                wait_list,
+                event,
+            )
+        })
+    }
+
+    #[cfg(any(feature = "clblast", feature = "clblast-dynamic"))]
+    unsafe fn enqueue_blas<F>(&self, f: F) -> Result<i32, ocl_core::Error>
+    where
+        F: FnOnce(*mut *mut c_void, *mut *mut c_void) -> i32,
+    {
+        let mut queue = self.queue().as_ptr();
+        if !self.records_events() {
+            return Ok(f(&mut queue, std::ptr::null_mut()));
+        }
+        // CLBlast takes no wait list, so dependencies are enforced with a barrier.
+        let wait_list = self.wait_list()?;
+        if !wait_list.is_empty() {
+            ocl_core::enqueue_barrier_with_wait_list(
+                self.queue(),
+                Some(&wait_list[..]),
+                None::<&mut Event>,
+                None,
+            )?;
+        }
+        let mut event = Event::null();
+        let status = f(&mut queue, event.as_ptr_mut());
+        if !event.is_null() {
+            self.record_event(Command::Gemm, event);
+        }
+        Ok(status)
+    }
+
+    fn program(&self, name: &'static str) -> Result<Program, OpenCLError> {
+        let mut programs = self.programs.lock().unwrap();
+        if let Some(program) = programs.get(name) {
+            return Ok(program.clone());
+        }
+        let (label, src) = program_source(name);
+        let program = self.build_program(&label, &src)?;
+        programs.insert(name, program.clone());
+        Ok(program)
+    }
+
+    fn build_all_programs(&self) -> Result<(), OpenCLError> {
+        for name in PROGRAM_NAMES {
+            self.program(*name)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    fn num_compiled_programs(&self) -> usize {
+        self.programs.lock().unwrap().len()
+    }
+
+    fn build_program(&self, name: &str, src: &str) -> Result<Program, OpenCLError> {
+        if let Some(cache) = &self.program_cache {
+            if let Some(binary) = cache.load(src) {
+                if let Ok(program) = self.build_program_from_binary(&binary) {
+                    return Ok(program);
+                }
+            }
+        }
+        let build_error = |log, e| OpenCLError::KernelBuild {
+            program: name.to_string(),
+            log: log,
+            error: e,
+        };
+        let src_cstring = CString::new(src).unwrap();
+        let program = ocl_core::create_program_with_source(&self.context, &[src_cstring])
+            .map_err(|e| build_error(String::new(), e))?;
+        ocl_core::build_program(&program, None::<&[()]>, &self.build_options, None, None)
+            .map_err(|e| build_error(self.build_log(&program), e))?;
+        if let Some(cache) = &self.program_cache {
+            if let Ok(ProgramInfoResult::Binaries(binaries)) =
+                ocl_core::get_program_info(&program, ProgramInfo::Binaries)
+            {
+                if let Some(binary) = binaries.first() {
+                    cache.store(src, binary);
+                }
+            }
+        }
+        Ok(program)
+    }
+
+    fn build_program_from_binary(&self, binary: &[u8]) -> Result<Program, ocl_core::Error> {
+        let device = self.queue.device()?;
+        let program = ocl_core::create_program_with_binary(&self.context, &[device], &[binary])?;
+        ocl_core::build_program(&program, None::<&[()]>, &self.build_options, None, None)?;
+        Ok(program)
+    }
+
+    fn build_log(&self, program: &Program) -> String {
+        let device = match self.queue.device() {
+            Ok(device) => device,
+            Err(_) => return String::new(),
+        };
+        match ocl_core::get_program_build_info(program, &device, ProgramBuildInfo::BuildLog) {
+            Ok(ProgramBuildInfoResult::BuildLog(log)) => log,
+            _ => String::new(),
+        }
+    }
+}
+
+impl Drop for OpenCLInternal {
+    fn drop(&mut self) {
+        for queue in self.queues() {
+            let _ = ocl_core::finish(queue);
+        }
+    }
+}
+
+lazy_static! {
+    static ref INSTANCES: Mutex<Vec<Weak<OpenCLInternal>>> = Mutex::new(vec![]);
 }
 
+#[derive(Clone)]
 pub struct OpenCL {
     internal: Arc<OpenCLInternal>,
 }
 
 impl OpenCL {
+    pub fn platforms() -> Result<Vec<PlatformProperties>, OpenCLError> {
+        properties::platforms()
+    }
+
+    pub fn devices() -> Result<Vec<DeviceProperties>, OpenCLError> {
+        properties::devices()
+    }
+
     pub fn new<'dev>(platform_id: usize, device_id: usize) -> Device<'dev> {
-        let internal = Arc::new(OpenCLInternal::new(platform_id, device_id));
+        match Self::try_new(platform_id, device_id) {
+            Ok(dev) => dev,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn select<'dev>(selector: DeviceSelector) -> Result<Device<'dev>, OpenCLError> {
+        OpenCLBuilder::new().selector(selector).build()
+    }
+
+    pub fn from_env<'dev>() -> Result<Device<'dev>, OpenCLError> {
+        OpenCLBuilder::new()
+            .selector(DeviceSelector::from_env()?)
+            .build()
+    }
+
+    pub fn try_new<'dev>(
+        platform_id: usize,
+        device_id: usize,
+    ) -> Result<Device<'dev>, OpenCLError> {
+        OpenCLBuilder::new().device(platform_id, device_id).build()
+    }
+
+    pub fn builder() -> OpenCLBuilder {
+        OpenCLBuilder::new()
+    }
+
+    pub fn from_device(dev: &Device) -> Option<OpenCL> {
+        OpenCLInternal::find(&dev.identifier()).map(|internal| OpenCL { internal: internal })
+    }
+
+    pub fn warm_up(&self) -> Result<(), OpenCLError> {
+        self.internal.build_all_programs()
+    }
+
+    pub fn profile_report(&self) -> Option<Result<ProfileReport, OpenCLError>> {
+        self.internal
+            .profiler
+            .as_ref()
+            .map(|profiler| profiler.report())
+    }
+
+    pub fn reset_profile(&self) {
+        if let Some(profiler) = &self.internal.profiler {
+            profiler.reset();
+        }
+    }
+
+    pub fn properties(&self) -> &DeviceProperties {
+        &self.internal.properties
+    }
+
+    pub fn flush(&self) -> Result<(), OpenCLError> {
+        for queue in self.internal.queues() {
+            ocl_core::flush(queue).map_err(OpenCLError::Queue)?;
+        }
+        Ok(())
+    }
+
+    pub fn synchronize(&self) -> Result<(), OpenCLError> {
+        for queue in self.internal.queues() {
+            ocl_core::finish(queue).map_err(OpenCLError::Queue)?;
+        }
+        Ok(())
+    }
+
+    pub fn fence(&self) -> Result<Fence, OpenCLError> {
+        Fence::new(self.internal.queue())
+    }
+
+    pub fn num_streams(&self) -> usize {
+        1 + self.internal.streams.len()
+    }
+
+    pub fn with_stream<R, F: FnOnce() -> R>(&self, stream: usize, f: F) -> R {
+        assert!(stream < self.num_streams());
+        let owner = &*self.internal as *const OpenCLInternal as usize;
+        stream::with_stream(owner, stream, f)
+    }
+
+    pub fn wait_on(&self, fence: &Fence) -> Result<(), OpenCLError> {
+        unsafe {
+            ocl_core::enqueue_barrier_with_wait_list(
+                self.internal.queue(),
+                Some(fence.event()),
+                None::<&mut Event>,
+                None,
+            )
+            .map_err(OpenCLError::Queue)
+        }
+    }
+
+    pub fn gemm_backend(&self) -> GemmBackend {
+        self.internal.gemm_backend()
+    }
+
+    pub fn out_of_order(&self) -> bool {
+        self.internal.dependencies.is_some()
+    }
+
+    pub fn zero_copy(&self) -> bool {
+        self.internal.zero_copy
+    }
+
+    pub fn view<'a>(&'a self, tensor: &'a Tensor) -> Result<TensorView<'a>, OpenCLError> {
+        assert_eq!(self.identifier(), tensor.device().identifier());
+        TensorView::new(&self.internal, tensor)
+    }
+
+    // Wraps a buffer created elsewhere in the device's context as a tensor. The buffer is
+    // retained by the tensor and released, not cached, when the tensor is dropped.
+    pub fn wrap_buffer<'dev>(
+        &self,
+        dev: &'dev Device<'dev>,
+        buffer: &Mem,
+        shape: Shape,
+    ) -> Result<Tensor<'dev>, OpenCLError> {
+        assert_eq!(self.identifier(), dev.identifier());
+        let context = match ocl_core::get_mem_object_info(buffer, MemInfo::Context)
+            .map_err(OpenCLError::BufferQuery)?
+        {
+            MemInfoResult::Context(context) => context,
+            _ => panic!(),
+        };
+        if context.as_ptr() != self.internal.context.as_ptr() {
+            return Err(OpenCLError::ForeignBuffer);
+        }
+        let size = match ocl_core::get_mem_object_info(buffer, MemInfo::Size)
+            .map_err(OpenCLError::BufferQuery)?
+        {
+            MemInfoResult::Size(size) => size,
+            _ => panic!(),
+        };
+        let required = shape.size() as usize * std::mem::size_of::<f32>();
+        if size < required {
+            return Err(OpenCLError::BufferTooSmall {
+                required: required,
+                size: size,
+            });
+        }
+        let mut tensor = dev.new_tensor(shape);
+        allocator::with_imported_buffer(buffer.clone(), || tensor.alloc());
+        Ok(tensor)
+    }
+
+    // Allocates a tensor like `Device::new_tensor` followed by `alloc`, but returns
+    // `OpenCLError::OutOfMemory` instead of panicking when the memory limit is exceeded or the
+    // driver cannot allocate the buffer.
+    pub fn try_new_tensor<'dev>(
+        &self,
+        dev: &'dev Device<'dev>,
+        shape: Shape,
+    ) -> Result<Tensor<'dev>, OpenCLError> {
+        assert_eq!(self.identifier(), dev.identifier());
+        let handle = self.internal.new_handle(shape.size() as usize)?;
+        let mut tensor = dev.new_tensor(shape);
+        allocator::with_allocated_handle(handle, || tensor.alloc());
+        Ok(tensor)
+    }
+
+    // The element offset and length of batch element `batch`, as taken by the `*_range_impl`
+    // functions.
+    pub fn batch_range(shape: &Shape, batch: u32) -> (u32, u32) {
+        assert!(batch < shape.batch());
+        (batch * shape.volume(), shape.volume())
+    }
+
+    pub fn batch_to_vector(&self, tensor: &Tensor, batch: u32) -> Vec<f32> {
+        assert_eq!(self.identifier(), tensor.device().identifier());
+        let (offset, len) = OpenCL::batch_range(&tensor.shape(), batch);
+        let mut ret = vec![0.; len as usize];
+        tensor.device().call_fw_f32_impl(
+            "tensor_to_vector_range_impl",
+            &[tensor],
+            &[offset],
+            &[],
+            &mut ret,
+        );
+        ret
+    }
+
+    // The buffer backing a tensor, for passing to other OpenCL code. Work enqueued on this
+    // device is not necessarily finished; use `fence()` or `synchronize()` first.
+    pub fn buffer<'a>(&self, tensor: &'a Tensor) -> &'a Mem {
+        assert_eq!(self.identifier(), tensor.device().identifier());
+        unsafe { transfer::tensor_buffer(tensor) }
+    }
+
+    pub fn read_async(&self, tensor: &Tensor) -> Result<PendingRead, OpenCLError> {
+        assert_eq!(self.identifier(), tensor.device().identifier());
+        let reads = self.internal.mem_keys(&[tensor]);
+        self.internal
+            .with_access(reads, vec![], || PendingRead::new(&self.internal, tensor))
+    }
+
+    pub fn memory_stats(&self) -> MemoryStats {
+        self.internal
+            .pool
+            .stats(self.internal.properties.global_mem_size)
+    }
+
+    pub fn set_memory_limit(&self, bytes: Option<usize>) {
+        self.internal.pool.set_limit(bytes);
+    }
+
+    pub fn reset_peak_memory(&self) {
+        self.internal.pool.reset_peak();
+    }
+
+    pub fn release_cached_memory(&self) {
+        self.internal.pool.release_cached();
+    }
+
+    pub fn cached_memory_bytes(&self) -> usize {
+        self.internal.pool.cached_bytes()
+    }
+
+    pub fn trace(&self) -> Option<Result<Trace, OpenCLError>> {
+        self.internal.tracer.as_ref().map(|tracer| tracer.trace())
+    }
+
+    pub fn reset_trace(&self) {
+        if let Some(tracer) = &self.internal.tracer {
+            tracer.reset();
+        }
+    }
+
+    fn register_impls<'dev>(internal: Arc<OpenCLInternal>) -> Result<Device<'dev>, OpenCLError> {
+        INSTANCES.lock().unwrap().push(Arc::downgrade(&internal));
         let mut dev = Device::new(OpenCL {
             internal: Arc::clone(&internal),
         });
 
         // initializers
 
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "reset_tensor_impl",
-            ops::reset_tensor::ResetTensorImpl::new(&internal),
+            internal,
+            ops::reset_tensor::ResetTensorImpl
         );
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "reset_tensor_by_slice_impl",
-            ops::reset_tensor::ResetTensorBySliceImpl::new(&internal),
+            internal,
+            ops::reset_tensor::ResetTensorBySliceImpl
+        );
+        register_impl!(
+            dev.register_fw_impl,
+            "reset_tensor_by_slice_range_impl",
+            internal,
+            ops::reset_tensor::ResetTensorBySliceRangeImpl
         );
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "reset_tensor_by_tensor_impl",
-            ops::reset_tensor::ResetTensorByTensorImpl::new(&internal),
+            internal,
+            ops::reset_tensor::ResetTensorByTensorImpl
         );
 
-        dev.register_fw_f32_impl(
+        register_impl!(
+            dev.register_fw_f32_impl,
             "tensor_to_vector_impl",
-            ops::tensor_to_vector::TensorToVectorImpl::new(&internal),
+            internal,
+            ops::tensor_to_vector::TensorToVectorImpl
+        );
+        register_impl!(
+            dev.register_fw_f32_impl,
+            "tensor_to_vector_range_impl",
+            internal,
+            ops::tensor_to_vector::TensorToVectorRangeImpl
         );
 
-        let identity_source = kernel_string!(identity);
-        let identity_program = internal.build_program(&identity_source);
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "identity_impl",
-            ops::identity::IdentityImpl::new(&identity_program, &internal),
+            internal,
+            identity => ops::identity::IdentityImpl
         );
 
         // TODO: random
 
-        let xorshift_source = kernel_string!(xorshift);
-        let xorshift_program = internal.build_program(&xorshift_source);
-        let randomizer = Arc::new(Mutex::new(ops::random::XORShiftRandomizer::new(
-            &xorshift_program,
-            &internal,
-        )));
-        dev.register_fw_impl(
+        let randomizer = Arc::new(lazy::Lazy::new(&internal, |internal| {
+            Ok(Mutex::new(ops::random::XORShiftRandomizer::new(
+                &internal.program("xorshift")?,
+                internal,
+            )))
+        }));
+        register_impl!(
+            dev.register_fw_impl,
             "random_bernoulli_impl",
-            ops::random::RandomBernoulliImpl::new(&randomizer, &xorshift_program, &internal),
+            internal,
+            xorshift, randomizer => ops::random::RandomBernoulliImpl
         );
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "random_normal_impl",
-            ops::random::RandomNormalImpl::new(&randomizer, &xorshift_program, &internal),
+            internal,
+            xorshift, randomizer => ops::random::RandomNormalImpl
         );
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "random_uniform_impl",
-            ops::random::RandomUniformImpl::new(&randomizer, &xorshift_program, &internal),
+            internal,
+            xorshift, randomizer => ops::random::RandomUniformImpl
         );
 
         // assign
 
-        let add_assign_source = kernel_string!(common) + &kernel_string!(add_assign);
-        let add_assign_program = internal.build_program(&add_assign_source);
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "add_assign_impl",
-            ops::add_assign::AddAssignImpl::new(&add_assign_program, &internal),
+            internal,
+            add_assign => ops::add_assign::AddAssignImpl
         );
 
-        let sub_assign_source = kernel_string!(common) + &kernel_string!(sub_assign);
-        let sub_assign_program = internal.build_program(&sub_assign_source);
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "sub_assign_impl",
-            ops::sub_assign::SubAssignImpl::new(&sub_assign_program, &internal),
+            internal,
+            sub_assign => ops::sub_assign::SubAssignImpl
         );
 
-        let mul_assign_source = kernel_string!(common) + &kernel_string!(mul_assign);
-        let mul_assign_program = internal.build_program(&mul_assign_source);
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "mul_assign_const_impl",
-            ops::mul_assign::MulAssignConstImpl::new(&mul_assign_program, &internal),
+            internal,
+            mul_assign => ops::mul_assign::MulAssignConstImpl
         );
 
         // utility
 
-        let argmax_source = kernel_string!(common) + &kernel_string!(argmax);
-        let argmax_program = internal.build_program(&argmax_source);
-        dev.register_fw_u32_impl(
+        register_impl!(
+            dev.register_fw_u32_impl,
             "argmax_impl",
-            ops::argmax::ArgmaxImpl::new(&argmax_program, &internal),
+            internal,
+            argmax => ops::argmax::ArgmaxImpl
         );
 
-        let argmin_source = kernel_string!(common) + &kernel_string!(argmin);
-        let argmin_program = internal.build_program(&argmin_source);
-        dev.register_fw_u32_impl(
+        register_impl!(
+            dev.register_fw_u32_impl,
             "argmin_impl",
-            ops::argmin::ArgminImpl::new(&argmin_program, &internal),
+            internal,
+            argmin => ops::argmin::ArgminImpl
         );
 
-        let argsort_source = kernel_string!(common) + &kernel_string!(argsort);
-        let argsort_program = internal.build_program(&argsort_source);
-        dev.register_fw_u32_impl(
+        register_impl!(
+            dev.register_fw_u32_impl,
             "argsort_impl",
-            ops::argsort::ArgsortImpl::new(&argsort_program, &internal),
+            internal,
+            argsort => ops::argsort::ArgsortImpl
         );
 
         // arithmetic
 
-        let neg_source = kernel_string!(common) + &kernel_string!(neg);
-        let neg_program = internal.build_program(&neg_source);
-        dev.register_fw_impl(
-            "neg_fw_impl",
-            ops::neg::NegFwImpl::new(&neg_program, &internal),
-        );
+        register_impl!(dev.register_fw_impl, "neg_fw_impl", internal, neg => ops::neg::NegFwImpl);
 
-        let add_source = kernel_string!(common) + &kernel_string!(add);
-        let add_program = internal.build_program(&add_source);
-        dev.register_fw_impl(
-            "add_fw_impl",
-            ops::add::AddFwImpl::new(&add_program, &internal),
-        );
-        dev.register_bw_impl(
+        register_impl!(dev.register_fw_impl, "add_fw_impl", internal, add => ops::add::AddFwImpl);
+        register_impl!(
+            dev.register_bw_impl,
             "add_bw_a_impl",
-            ops::add::AddBwAImpl::new(&add_program, &internal),
+            internal,
+            add => ops::add::AddBwAImpl
         );
-        dev.register_bw_impl(
+        register_impl!(
+            dev.register_bw_impl,
             "add_bw_b_impl",
-            ops::add::AddBwBImpl::new(&add_program, &internal),
+            internal,
+            add => ops::add::AddBwBImpl
         );
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "add_const_fw_impl",
-            ops::add::AddConstFwImpl::new(&add_program, &internal),
+            internal,
+            add => ops::add::AddConstFwImpl
         );
-        dev.register_bw_impl(
+        register_impl!(
+            dev.register_bw_impl,
             "add_const_bw_impl",
-            ops::add::AddConstBwImpl::new(&add_program, &internal),
+            internal,
+            add => ops::add::AddConstBwImpl
         );
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "add_scalar_fw_impl",
-            ops::add::AddScalarFwImpl::new(&add_program, &internal),
+            internal,
+            add => ops::add::AddScalarFwImpl
         );
 
-        let sub_source = kernel_string!(common) + &kernel_string!(sub);
-        let sub_program = internal.build_program(&sub_source);
-        dev.register_fw_impl(
-            "sub_fw_impl",
-            ops::sub::SubFwImpl::new(&sub_program, &internal),
-        );
-        dev.register_bw_impl(
+        register_impl!(dev.register_fw_impl, "sub_fw_impl", internal, sub => ops::sub::SubFwImpl);
+        register_impl!(
+            dev.register_bw_impl,
             "sub_bw_a_impl",
-            ops::sub::SubBwAImpl::new(&sub_program, &internal),
+            internal,
+            sub => ops::sub::SubBwAImpl
         );
-        dev.register_bw_impl(
+        register_impl!(
+            dev.register_bw_impl,
             "sub_bw_b_impl",
-            ops::sub::SubBwBImpl::new(&sub_program, &internal),
+            internal,
+            sub => ops::sub::SubBwBImpl
         );
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "sub_const_l_fw_impl",
-            ops::sub::SubConstLFwImpl::new(&sub_program, &internal),
+            internal,
+            sub => ops::sub::SubConstLFwImpl
         );
-        dev.register_bw_impl(
+        register_impl!(
+            dev.register_bw_impl,
             "sub_const_l_bw_impl",
-            ops::sub::SubConstLBwImpl::new(&sub_program, &internal),
+            internal,
+            sub => ops::sub::SubConstLBwImpl
         );
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "sub_const_r_fw_impl",
-            ops::sub::SubConstRFwImpl::new(&sub_program, &internal),
+            internal,
+            sub => ops::sub::SubConstRFwImpl
         );
-        dev.register_bw_impl(
+        register_impl!(
+            dev.register_bw_impl,
             "sub_const_r_bw_impl",
-            ops::sub::SubConstRBwImpl::new(&sub_program, &internal),
+            internal,
+            sub => ops::sub::SubConstRBwImpl
         );
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "sub_scalar_l_fw_impl",
-            ops::sub::SubScalarLFwImpl::new(&sub_program, &internal),
+            internal,
+            sub => ops::sub::SubScalarLFwImpl
         );
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "sub_scalar_r_fw_impl",
-            ops::sub::SubScalarRFwImpl::new(&sub_program, &internal),
+            internal,
+            sub => ops::sub::SubScalarRFwImpl
         );
 
-        let mul_source = kernel_string!(common) + &kernel_string!(mul);
-        let mul_program = internal.build_program(&mul_source);
-        dev.register_fw_impl(
-            "mul_fw_impl",
-            ops::mul::MulFwImpl::new(&mul_program, &internal),
-        );
-        dev.register_bw_impl(
+        register_impl!(dev.register_fw_impl, "mul_fw_impl", internal, mul => ops::mul::MulFwImpl);
+        register_impl!(
+            dev.register_bw_impl,
             "mul_bw_a_impl",
-            ops::mul::MulBwAImpl::new(&mul_program, &internal),
+            internal,
+            mul => ops::mul::MulBwAImpl
         );
-        dev.register_bw_impl(
+        register_impl!(
+            dev.register_bw_impl,
             "mul_bw_b_impl",
-            ops::mul::MulBwBImpl::new(&mul_program, &internal),
+            internal,
+            mul => ops::mul::MulBwBImpl
         );
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "mul_const_fw_impl",
-            ops::mul::MulConstFwImpl::new(&mul_program, &internal),
+            internal,
+            mul => ops::mul::MulConstFwImpl
         );
-        dev.register_bw_impl(
+        register_impl!(
+            dev.register_bw_impl,
             "mul_const_bw_impl",
-            ops::mul::MulConstBwImpl::new(&mul_program, &internal),
+            internal,
+            mul => ops::mul::MulConstBwImpl
         );
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "mul_scalar_fw_impl",
-            ops::mul::MulScalarFwImpl::new(&mul_program, &internal),
+            internal,
+            mul => ops::mul::MulScalarFwImpl
         );
 
-        let div_source = kernel_string!(common) + &kernel_string!(div);
-        let div_program = internal.build_program(&div_source);
-        dev.register_fw_impl(
-            "div_fw_impl",
-            ops::div::DivFwImpl::new(&div_program, &internal),
-        );
-        dev.register_bw_impl(
+        register_impl!(dev.register_fw_impl, "div_fw_impl", internal, div => ops::div::DivFwImpl);
+        register_impl!(
+            dev.register_bw_impl,
             "div_bw_a_impl",
-            ops::div::DivBwAImpl::new(&div_program, &internal),
+            internal,
+            div => ops::div::DivBwAImpl
         );
-        dev.register_bw_impl(
+        register_impl!(
+            dev.register_bw_impl,
             "div_bw_b_impl",
-            ops::div::DivBwBImpl::new(&div_program, &internal),
+            internal,
+            div => ops::div::DivBwBImpl
         );
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "div_const_l_fw_impl",
-            ops::div::DivConstLFwImpl::new(&div_program, &internal),
+            internal,
+            div => ops::div::DivConstLFwImpl
         );
-        dev.register_bw_impl(
+        register_impl!(
+            dev.register_bw_impl,
             "div_const_l_bw_impl",
-            ops::div::DivConstLBwImpl::new(&div_program, &internal),
+            internal,
+            div => ops::div::DivConstLBwImpl
         );
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "div_const_r_fw_impl",
-            ops::div::DivConstRFwImpl::new(&div_program, &internal),
+            internal,
+            div => ops::div::DivConstRFwImpl
         );
-        dev.register_bw_impl(
+        register_impl!(
+            dev.register_bw_impl,
             "div_const_r_bw_impl",
-            ops::div::DivConstRBwImpl::new(&div_program, &internal),
+            internal,
+            div => ops::div::DivConstRBwImpl
         );
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "div_scalar_l_fw_impl",
-            ops::div::DivScalarLFwImpl::new(&div_program, &internal),
+            internal,
+            div => ops::div::DivScalarLFwImpl
         );
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "div_scalar_r_fw_impl",
-            ops::div::DivScalarRFwImpl::new(&div_program, &internal),
+            internal,
+            div => ops::div::DivScalarRFwImpl
         );
 
         // basic
 
-        let powf_source = kernel_string!(common) + &kernel_string!(powf);
-        let powf_program = internal.build_program(&powf_source);
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "powf_fw_impl",
-            ops::powf::PowfFwImpl::new(&powf_program, &internal),
+            internal,
+            powf => ops::powf::PowfFwImpl
         );
-        dev.register_bw_impl(
+        register_impl!(
+            dev.register_bw_impl,
             "powf_bw_a_impl",
-            ops::powf::PowfBwAImpl::new(&powf_program, &internal),
+            internal,
+            powf => ops::powf::PowfBwAImpl
         );
-        dev.register_bw_impl(
+        register_impl!(
+            dev.register_bw_impl,
             "powf_bw_b_impl",
-            ops::powf::PowfBwBImpl::new(&powf_program, &internal),
+            internal,
+            powf => ops::powf::PowfBwBImpl
         );
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "powf_const_l_fw_impl",
-            ops::powf::PowfConstLFwImpl::new(&powf_program, &internal),
+            internal,
+            powf => ops::powf::PowfConstLFwImpl
         );
-        dev.register_bw_impl(
+        register_impl!(
+            dev.register_bw_impl,
             "powf_const_l_bw_impl",
-            ops::powf::PowfConstLBwImpl::new(&powf_program, &internal),
+            internal,
+            powf => ops::powf::PowfConstLBwImpl
         );
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "powf_const_r_fw_impl",
-            ops::powf::PowfConstRFwImpl::new(&powf_program, &internal),
+            internal,
+            powf => ops::powf::PowfConstRFwImpl
         );
-        dev.register_bw_impl(
+        register_impl!(
+            dev.register_bw_impl,
             "powf_const_r_bw_impl",
-            ops::powf::PowfConstRBwImpl::new(&powf_program, &internal),
+            internal,
+            powf => ops::powf::PowfConstRBwImpl
         );
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "powf_scalar_l_fw_impl",
-            ops::powf::PowfScalarLFwImpl::new(&powf_program, &internal),
+            internal,
+            powf => ops::powf::PowfScalarLFwImpl
         );
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "powf_scalar_r_fw_impl",
-            ops::powf::PowfScalarRFwImpl::new(&powf_program, &internal),
+            internal,
+            powf => ops::powf::PowfScalarRFwImpl
         );
 
-        let sqrt_source = kernel_string!(common) + &kernel_string!(sqrt);
-        let sqrt_program = internal.build_program(&sqrt_source);
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "sqrt_fw_impl",
-            ops::sqrt::SqrtFwImpl::new(&sqrt_program, &internal),
+            internal,
+            sqrt => ops::sqrt::SqrtFwImpl
         );
-        dev.register_bw_impl(
+        register_impl!(
+            dev.register_bw_impl,
             "sqrt_bw_impl",
-            ops::sqrt::SqrtBwImpl::new(&sqrt_program, &internal),
+            internal,
+            sqrt => ops::sqrt::SqrtBwImpl
         );
 
-        let abs_source = kernel_string!(common) + &kernel_string!(abs);
-        let abs_program = internal.build_program(&abs_source);
-        dev.register_fw_impl(
-            "abs_fw_impl",
-            ops::abs::AbsFwImpl::new(&abs_program, &internal),
-        );
-        dev.register_bw_impl(
-            "abs_bw_impl",
-            ops::abs::AbsBwImpl::new(&abs_program, &internal),
-        );
+        register_impl!(dev.register_fw_impl, "abs_fw_impl", internal, abs => ops::abs::AbsFwImpl);
+        register_impl!(dev.register_bw_impl, "abs_bw_impl", internal, abs => ops::abs::AbsBwImpl);
 
-        let powi_source = kernel_string!(common) + &kernel_string!(powi);
-        let powi_program = internal.build_program(&powi_source);
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "powi_fw_impl",
-            ops::powi::PowiFwImpl::new(&powi_program, &internal),
+            internal,
+            powi => ops::powi::PowiFwImpl
         );
-        dev.register_bw_impl(
+        register_impl!(
+            dev.register_bw_impl,
             "powi_bw_impl",
-            ops::powi::PowiBwImpl::new(&powi_program, &internal),
+            internal,
+            powi => ops::powi::PowiBwImpl
         );
 
         // trigonometric
 
-        let sin_source = kernel_string!(common) + &kernel_string!(sin);
-        let sin_program = internal.build_program(&sin_source);
-        dev.register_fw_impl(
-            "sin_fw_impl",
-            ops::sin::SinFwImpl::new(&sin_program, &internal),
-        );
-        dev.register_bw_impl(
-            "sin_bw_impl",
-            ops::sin::SinBwImpl::new(&sin_program, &internal),
-        );
+        register_impl!(dev.register_fw_impl, "sin_fw_impl", internal, sin => ops::sin::SinFwImpl);
+        register_impl!(dev.register_bw_impl, "sin_bw_impl", internal, sin => ops::sin::SinBwImpl);
 
-        let cos_source = kernel_string!(common) + &kernel_string!(cos);
-        let cos_program = internal.build_program(&cos_source);
-        dev.register_fw_impl(
-            "cos_fw_impl",
-            ops::cos::CosFwImpl::new(&cos_program, &internal),
-        );
-        dev.register_bw_impl(
-            "cos_bw_impl",
-            ops::cos::CosBwImpl::new(&cos_program, &internal),
-        );
+        register_impl!(dev.register_fw_impl, "cos_fw_impl", internal, cos => ops::cos::CosFwImpl);
+        register_impl!(dev.register_bw_impl, "cos_bw_impl", internal, cos => ops::cos::CosBwImpl);
 
-        let tan_source = kernel_string!(common) + &kernel_string!(tan);
-        let tan_program = internal.build_program(&tan_source);
-        dev.register_fw_impl(
-            "tan_fw_impl",
-            ops::tan::TanFwImpl::new(&tan_program, &internal),
-        );
-        dev.register_bw_impl(
-            "tan_bw_impl",
-            ops::tan::TanBwImpl::new(&tan_program, &internal),
-        );
+        register_impl!(dev.register_fw_impl, "tan_fw_impl", internal, tan => ops::tan::TanFwImpl);
+        register_impl!(dev.register_bw_impl, "tan_bw_impl", internal, tan => ops::tan::TanBwImpl);
 
         // exp
 
-        let exp_source = kernel_string!(common) + &kernel_string!(exp);
-        let exp_program = internal.build_program(&exp_source);
-        dev.register_fw_impl(
-            "exp_fw_impl",
-            ops::exp::ExpFwImpl::new(&exp_program, &internal),
-        );
-        dev.register_bw_impl(
-            "exp_bw_impl",
-            ops::exp::ExpBwImpl::new(&exp_program, &internal),
-        );
+        register_impl!(dev.register_fw_impl, "exp_fw_impl", internal, exp => ops::exp::ExpFwImpl);
+        register_impl!(dev.register_bw_impl, "exp_bw_impl", internal, exp => ops::exp::ExpBwImpl);
 
-        let ln_source = kernel_string!(common) + &kernel_string!(ln);
-        let ln_program = internal.build_program(&ln_source);
-        dev.register_fw_impl("ln_fw_impl", ops::ln::LnFwImpl::new(&ln_program, &internal));
-        dev.register_bw_impl("ln_bw_impl", ops::ln::LnBwImpl::new(&ln_program, &internal));
+        register_impl!(dev.register_fw_impl, "ln_fw_impl", internal, ln => ops::ln::LnFwImpl);
+        register_impl!(dev.register_bw_impl, "ln_bw_impl", internal, ln => ops::ln::LnBwImpl);
 
-        let tanh_source = kernel_string!(common) + &kernel_string!(tanh);
-        let tanh_program = internal.build_program(&tanh_source);
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "tanh_fw_impl",
-            ops::tanh::TanhFwImpl::new(&tanh_program, &internal),
+            internal,
+            tanh => ops::tanh::TanhFwImpl
         );
-        dev.register_bw_impl(
+        register_impl!(
+            dev.register_bw_impl,
             "tanh_bw_impl",
-            ops::tanh::TanhBwImpl::new(&tanh_program, &internal),
+            internal,
+            tanh => ops::tanh::TanhBwImpl
         );
 
-        let sigmoid_source = kernel_string!(common) + &kernel_string!(sigmoid);
-        let sigmoid_program = internal.build_program(&sigmoid_source);
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "sigmoid_fw_impl",
-            ops::sigmoid::SigmoidFwImpl::new(&sigmoid_program, &internal),
+            internal,
+            sigmoid => ops::sigmoid::SigmoidFwImpl
         );
-        dev.register_bw_impl(
+        register_impl!(
+            dev.register_bw_impl,
             "sigmoid_bw_impl",
-            ops::sigmoid::SigmoidBwImpl::new(&sigmoid_program, &internal),
+            internal,
+            sigmoid => ops::sigmoid::SigmoidBwImpl
         );
 
-        let softplus_source = kernel_string!(common) + &kernel_string!(softplus);
-        let softplus_program = internal.build_program(&softplus_source);
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "softplus_fw_impl",
-            ops::softplus::SoftplusFwImpl::new(&softplus_program, &internal),
+            internal,
+            softplus => ops::softplus::SoftplusFwImpl
         );
 
         // reduction
 
-        let sum_source = kernel_string!(common) + &kernel_string!(sum);
-        let sum_program = internal.build_program(&sum_source);
-        dev.register_fw_impl(
-            "sum_fw_impl",
-            ops::sum::SumFwImpl::new(&sum_program, &internal),
-        );
+        register_impl!(dev.register_fw_impl, "sum_fw_impl", internal, sum => ops::sum::SumFwImpl);
 
-        let logsumexp_source = kernel_string!(common) + &kernel_string!(logsumexp);
-        let logsumexp_program = internal.build_program(&logsumexp_source);
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "logsumexp_fw_impl",
-            ops::logsumexp::LogsumexpFwImpl::new(&logsumexp_program, &internal),
+            internal,
+            logsumexp => ops::logsumexp::LogsumexpFwImpl
         );
 
-        let max_source = kernel_string!(common) + &kernel_string!(max);
-        let max_program = internal.build_program(&max_source);
-        dev.register_fw_impl(
-            "max_fw_impl",
-            ops::max::MaxFwImpl::new(&max_program, &internal),
-        );
-        dev.register_bw_impl(
-            "max_bw_impl",
-            ops::max::MaxBwImpl::new(&max_program, &internal),
-        );
+        register_impl!(dev.register_fw_impl, "max_fw_impl", internal, max => ops::max::MaxFwImpl);
+        register_impl!(dev.register_bw_impl, "max_bw_impl", internal, max => ops::max::MaxBwImpl);
 
-        let min_source = kernel_string!(common) + &kernel_string!(min);
-        let min_program = internal.build_program(&min_source);
-        dev.register_fw_impl(
-            "min_fw_impl",
-            ops::min::MinFwImpl::new(&min_program, &internal),
-        );
-        dev.register_bw_impl(
-            "min_bw_impl",
-            ops::min::MinBwImpl::new(&min_program, &internal),
-        );
+        register_impl!(dev.register_fw_impl, "min_fw_impl", internal, min => ops::min::MinFwImpl);
+        register_impl!(dev.register_bw_impl, "min_bw_impl", internal, min => ops::min::MinBwImpl);
 
-        let broadcast_source = kernel_string!(common) + &kernel_string!(broadcast);
-        let broadcast_program = internal.build_program(&broadcast_source);
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "broadcast_fw_impl",
-            ops::broadcast::BroadcastFwImpl::new(&broadcast_program, &internal),
+            internal,
+            broadcast => ops::broadcast::BroadcastFwImpl
         );
 
         // matrix
 
-        dev.register_fw_impl("matmul_fw_impl", ops::matmul::MatmulFwImpl::new(&internal));
-        dev.register_bw_impl(
+        register_impl!(
+            dev.register_fw_impl,
+            "matmul_fw_impl",
+            internal,
+            ops::matmul::MatmulFwImpl
+        );
+        register_impl!(
+            dev.register_bw_impl,
             "matmul_bw_a_impl",
-            ops::matmul::MatmulBwAImpl::new(&internal),
+            internal,
+            ops::matmul::MatmulBwAImpl
         );
-        dev.register_bw_impl(
+        register_impl!(
+            dev.register_bw_impl,
             "matmul_bw_b_impl",
-            ops::matmul::MatmulBwBImpl::new(&internal),
-        );
-
-        let transpose_source = kernel_string!(common) + &kernel_string!(transpose);
-        let transpose_program = internal.build_program(&transpose_source);
-        dev.register_fw_impl(
+            internal,
+            ops::matmul::MatmulBwBImpl
+        );
+        register_impl!(
+            dev.register_fw_impl,
+            "matmul_tn_fw_impl",
+            internal,
+            ops::matmul::MatmulTnFwImpl
+        );
+        register_impl!(
+            dev.register_bw_impl,
+            "matmul_tn_bw_a_impl",
+            internal,
+            ops::matmul::MatmulTnBwAImpl
+        );
+        register_impl!(
+            dev.register_bw_impl,
+            "matmul_tn_bw_b_impl",
+            internal,
+            ops::matmul::MatmulTnBwBImpl
+        );
+        register_impl!(
+            dev.register_fw_impl,
+            "matmul_nt_fw_impl",
+            internal,
+            ops::matmul::MatmulNtFwImpl
+        );
+        register_impl!(
+            dev.register_bw_impl,
+            "matmul_nt_bw_a_impl",
+            internal,
+            ops::matmul::MatmulNtBwAImpl
+        );
+        register_impl!(
+            dev.register_bw_impl,
+            "matmul_nt_bw_b_impl",
+            internal,
+            ops::matmul::MatmulNtBwBImpl
+        );
+        register_impl!(
+            dev.register_fw_impl,
+            "matmul_tt_fw_impl",
+            internal,
+            ops::matmul::MatmulTtFwImpl
+        );
+        register_impl!(
+            dev.register_bw_impl,
+            "matmul_tt_bw_a_impl",
+            internal,
+            ops::matmul::MatmulTtBwAImpl
+        );
+        register_impl!(
+            dev.register_bw_impl,
+            "matmul_tt_bw_b_impl",
+            internal,
+            ops::matmul::MatmulTtBwBImpl
+        );
+
+        register_impl!(
+            dev.register_fw_impl,
             "transpose_fw_impl",
-            ops::transpose::TransposeFwImpl::new(&transpose_program, &internal),
+            internal,
+            transpose => ops::transpose::TransposeFwImpl
         );
-        dev.register_bw_impl(
+        register_impl!(
+            dev.register_bw_impl,
             "transpose_bw_impl",
-            ops::transpose::TransposeBwImpl::new(&transpose_program, &internal),
+            internal,
+            transpose => ops::transpose::TransposeBwImpl
         );
 
-        let permute_dims_source = kernel_string!(common) + &kernel_string!(permute_dims);
-        let permute_dims_program = internal.build_program(&permute_dims_source);
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "permute_dims_fw_impl",
-            ops::permute_dims::PermuteDimsFwImpl::new(&permute_dims_program, &internal),
+            internal,
+            permute_dims => ops::permute_dims::PermuteDimsFwImpl
         );
-        dev.register_bw_impl(
+        register_impl!(
+            dev.register_bw_impl,
             "permute_dims_bw_impl",
-            ops::permute_dims::PermuteDimsBwImpl::new(&permute_dims_program, &internal),
+            internal,
+            permute_dims => ops::permute_dims::PermuteDimsBwImpl
         );
 
-        let flip_source = kernel_string!(common) + &kernel_string!(flip);
-        let flip_program = internal.build_program(&flip_source);
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "flip_fw_impl",
-            ops::flip::FlipFwImpl::new(&flip_program, &internal),
+            internal,
+            flip => ops::flip::FlipFwImpl
         );
-        dev.register_bw_impl(
+        register_impl!(
+            dev.register_bw_impl,
             "flip_bw_impl",
-            ops::flip::FlipBwImpl::new(&flip_program, &internal),
+            internal,
+            flip => ops::flip::FlipBwImpl
         );
 
-        let triangular_l_source = kernel_string!(common) + &kernel_string!(triangular_l);
-        let triangular_l_program = internal.build_program(&triangular_l_source);
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "triangular_l_fw_impl",
-            ops::triangular_l::TriangularLFwImpl::new(&triangular_l_program, &internal),
+            internal,
+            triangular_l => ops::triangular_l::TriangularLFwImpl
         );
-        dev.register_bw_impl(
+        register_impl!(
+            dev.register_bw_impl,
             "triangular_l_bw_impl",
-            ops::triangular_l::TriangularLBwImpl::new(&triangular_l_program, &internal),
+            internal,
+            triangular_l => ops::triangular_l::TriangularLBwImpl
         );
 
-        let triangular_u_source = kernel_string!(common) + &kernel_string!(triangular_u);
-        let triangular_u_program = internal.build_program(&triangular_u_source);
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "triangular_u_fw_impl",
-            ops::triangular_u::TriangularUFwImpl::new(&triangular_u_program, &internal),
+            internal,
+            triangular_u => ops::triangular_u::TriangularUFwImpl
         );
-        dev.register_bw_impl(
+        register_impl!(
+            dev.register_bw_impl,
             "triangular_u_bw_impl",
-            ops::triangular_u::TriangularUBwImpl::new(&triangular_u_program, &internal),
+            internal,
+            triangular_u => ops::triangular_u::TriangularUBwImpl
         );
 
         // ramp
 
-        let prelu_source = kernel_string!(common) + &kernel_string!(prelu);
-        let prelu_program = internal.build_program(&prelu_source);
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "prelu_fw_impl",
-            ops::prelu::PReLUFwImpl::new(&prelu_program, &internal),
+            internal,
+            prelu => ops::prelu::PReLUFwImpl
         );
-        dev.register_bw_impl(
+        register_impl!(
+            dev.register_bw_impl,
             "prelu_bw_impl",
-            ops::prelu::PReLUBwImpl::new(&prelu_program, &internal),
+            internal,
+            prelu => ops::prelu::PReLUBwImpl
         );
 
-        let elu_source = kernel_string!(common) + &kernel_string!(elu);
-        let elu_program = internal.build_program(&elu_source);
-        dev.register_fw_impl(
-            "elu_fw_impl",
-            ops::elu::EluFwImpl::new(&elu_program, &internal),
-        );
-        dev.register_bw_impl(
-            "elu_bw_impl",
-            ops::elu::EluBwImpl::new(&elu_program, &internal),
-        );
+        register_impl!(dev.register_fw_impl, "elu_fw_impl", internal, elu => ops::elu::EluFwImpl);
+        register_impl!(dev.register_bw_impl, "elu_bw_impl", internal, elu => ops::elu::EluBwImpl);
 
         // manipulation
 
-        let slice_source = kernel_string!(common) + &kernel_string!(slice);
-        let slice_program = internal.build_program(&slice_source);
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "slice_fw_impl",
-            ops::slice::SliceFwImpl::new(&slice_program, &internal),
+            internal,
+            slice => ops::slice::SliceFwImpl
         );
-        dev.register_bw_impl(
+        register_impl!(
+            dev.register_bw_impl,
             "slice_bw_impl",
-            ops::slice::SliceBwImpl::new(&slice_program, &internal),
+            internal,
+            slice => ops::slice::SliceBwImpl
         );
 
-        let pick_source = kernel_string!(common) + &kernel_string!(pick);
-        let pick_program = internal.build_program(&pick_source);
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "pick_fw_impl",
-            ops::pick::PickFwImpl::new(&pick_program, &internal),
+            internal,
+            pick => ops::pick::PickFwImpl
         );
-        dev.register_bw_impl(
+        register_impl!(
+            dev.register_bw_impl,
             "pick_bw_impl",
-            ops::pick::PickBwImpl::new(&pick_program, &internal),
+            internal,
+            pick => ops::pick::PickBwImpl
         );
 
-        let concat_source = kernel_string!(common) + &kernel_string!(concat);
-        let concat_program = internal.build_program(&concat_source);
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "concat_fw_impl",
-            ops::concat::ConcatFwImpl::new(&concat_program, &internal),
+            internal,
+            concat => ops::concat::ConcatFwImpl
         );
 
         // batch
 
-        let batch_concat_source = kernel_string!(common) + &kernel_string!(batch_concat);
-        let batch_concat_program = internal.build_program(&batch_concat_source);
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "batch_concat_fw_impl",
-            ops::batch_concat::BatchConcatFwImpl::new(&batch_concat_program, &internal),
+            internal,
+            batch_concat => ops::batch_concat::BatchConcatFwImpl
         );
 
-        let batch_pick_source = kernel_string!(common) + &kernel_string!(batch_pick);
-        let batch_pick_program = internal.build_program(&batch_pick_source);
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "batch_pick_fw_impl",
-            ops::batch_pick::BatchPickFwImpl::new(&batch_pick_program, &internal),
+            internal,
+            batch_pick => ops::batch_pick::BatchPickFwImpl
         );
-        dev.register_bw_impl(
+        register_impl!(
+            dev.register_bw_impl,
             "batch_pick_bw_impl",
-            ops::batch_pick::BatchPickBwImpl::new(&batch_pick_program, &internal),
+            internal,
+            batch_pick => ops::batch_pick::BatchPickBwImpl
         );
 
-        let batch_slice_source = kernel_string!(common) + &kernel_string!(batch_slice);
-        let batch_slice_program = internal.build_program(&batch_slice_source);
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "batch_slice_fw_impl",
-            ops::batch_slice::BatchSliceFwImpl::new(&batch_slice_program, &internal),
+            internal,
+            batch_slice => ops::batch_slice::BatchSliceFwImpl
         );
-        dev.register_bw_impl(
+        register_impl!(
+            dev.register_bw_impl,
             "batch_slice_bw_impl",
-            ops::batch_slice::BatchSliceBwImpl::new(&batch_slice_program, &internal),
+            internal,
+            batch_slice => ops::batch_slice::BatchSliceBwImpl
         );
 
-        let batch_sum_source = kernel_string!(common) + &kernel_string!(batch_sum);
-        let batch_sum_program = internal.build_program(&batch_sum_source);
-        dev.register_fw_impl(
+        register_impl!(
+            dev.register_fw_impl,
             "batch_sum_fw_impl",
-            ops::batch_sum::BatchSumFwImpl::new(&batch_sum_program, &internal),
+            internal,
+            batch_sum => ops::batch_sum::BatchSumFwImpl
         );
 
-        dev
+        Ok(dev)
     }
 }
 
 impl DeviceImpl for OpenCL {
     fn identifier(&self) -> String {
-        "OpenCL,".to_string() + &(self.internal.context.as_ptr() as usize).to_string()
+        self.internal.identifier()
     }
 
     fn new_handle(&self, size: u32) -> AtomicPtr<c_void> {
-        let buffer = unsafe {
-            ocl_core::create_buffer(
-                &self.internal.context,
-                ocl_core::MEM_READ_WRITE,
-                size as usize,
-                None::<&[f32]>,
-            )
-            .unwrap()
-        };
-        AtomicPtr::new(Box::into_raw(Box::new(buffer)) as *mut c_void)
+        match self.internal.new_handle(size as usize) {
+            Ok(handle) => handle,
+            // `DeviceImpl` cannot return errors.
+            Err(e) => panic!("{}", e),
+        }
     }
 
     fn drop_handle(&self, handle: &AtomicPtr<c_void>) {
-        unsafe {
-            Box::from_raw(handle.load(Ordering::Acquire) as *mut Mem);
+        self.internal
+            .pool
+            .drop_handle(handle, self.internal.release_events());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{allocator, OpenCL, OpenCLError};
+    use prima_undine::functions::BasicFunctions;
+    use prima_undine::shape;
+
+    #[test]
+    fn check_wrap_buffer() {
+        let dev = OpenCL::from_env().unwrap();
+        let cl = OpenCL::from_device(&dev).unwrap();
+        let x = dev.new_tensor_by_slice(shape![2, 2], &[1., 2., 3., 4.]);
+        let buffer = cl.buffer(&x).clone();
+        {
+            let mut y = cl.wrap_buffer(&dev, &buffer, shape![2, 2]).unwrap();
+            assert_vector_ulps_eq!(vec![1., 2., 3., 4.], y.to_vec());
+            dev.call_fw_impl("reset_tensor_impl", &[], &[], &[0.], &mut [&mut y]);
+            // Wrapped buffers are not counted.
+            assert_eq!(1, cl.memory_stats().live_handles);
+        }
+        assert_eq!(0, cl.cached_memory_bytes());
+        assert_vector_ulps_eq!(vec![0.; 4], x.to_vec());
+
+        match cl.wrap_buffer(&dev, &buffer, shape![3, 3; 8]) {
+            Err(OpenCLError::BufferTooSmall { required, size }) => {
+                assert_eq!(288, required);
+                assert_eq!(allocator::size_class(4) * 4, size);
+            }
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("buffer must not be wrapped"),
+        }
+        let other = crate::test_utils::get_device().new_tensor_by_constant(shape![2, 2], 0.);
+        let other_cl = OpenCL::from_device(crate::test_utils::get_device()).unwrap();
+        match cl.wrap_buffer(&dev, other_cl.buffer(&other), shape![2, 2]) {
+            Err(OpenCLError::ForeignBuffer) => {}
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("buffer must not be wrapped"),
         }
     }
 }
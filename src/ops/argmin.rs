@@ -2,7 +2,6 @@ use std::sync::Arc;
 use std::sync::Mutex;
 
 use ocl_core::ArgVal;
-use ocl_core::Event;
 use ocl_core::Kernel;
 use ocl_core::Program;
 
@@ -40,8 +39,16 @@ impl FunctionFwU32Impl for ArgminImpl {
         let x = xs[0];
         let dim = u32data[0];
         let n = x.shape()[dim];
-        let r = x.shape().size() / n;
         let s = x.shape().lower_volume(dim);
+        // With a second argument, only that batch element is reduced.
+        let (first, r) = match u32data.get(1) {
+            Some(&batch) => {
+                let (offset, len) = crate::OpenCL::batch_range(&x.shape(), batch);
+                (offset / n, len / n)
+            }
+            None => (0, x.shape().size() / n),
+        };
+        assert_eq!(r as usize, ys.len());
         // TODO
         let mut group_size = 256;
         while group_size >> 1 >= n {
@@ -62,18 +69,11 @@ impl FunctionFwU32Impl for ArgminImpl {
                 ocl_core::set_kernel_arg(&kernel, 0, ArgVal::mem(buffer!(x))).unwrap();
                 ocl_core::set_kernel_arg(&kernel, 1, ArgVal::scalar(&s)).unwrap();
                 ocl_core::set_kernel_arg(&kernel, 2, ArgVal::scalar(&n)).unwrap();
-                ocl_core::set_kernel_arg(&kernel, 3, ArgVal::mem(&ret)).unwrap();
-                ocl_core::enqueue_kernel(
-                    &self.internal.queue,
-                    &kernel,
-                    1,
-                    None,
-                    &[r as usize * k, 1, 1],
-                    Some([k, 1, 1]),
-                    None::<Event>,
-                    None::<&mut Event>,
-                )
-                .unwrap();
+                ocl_core::set_kernel_arg(&kernel, 3, ArgVal::scalar(&first)).unwrap();
+                ocl_core::set_kernel_arg(&kernel, 4, ArgVal::mem(&ret)).unwrap();
+                self.internal
+                    .enqueue_kernel(&kernel, 1, None, &[r as usize * k, 1, 1], Some([k, 1, 1]))
+                    .unwrap();
             }
         };
         match group_size {
@@ -91,7 +91,7 @@ impl FunctionFwU32Impl for ArgminImpl {
             _ => panic!(),
         }
         unsafe {
-            super::common::read_buffer(&self.internal.queue, &ret, ys);
+            super::common::read_buffer(&self.internal, &ret, ys);
         }
     }
 }
@@ -124,6 +124,26 @@ mod tests {
         }
     }
 
+    #[test]
+    fn check_argmin_batch() {
+        let x_data = vec![
+            3., 4., 5., 0., 1., 2., 6., 7., 8., 0., -1., -2., -6., -7., -8., -3., -4., -5.,
+        ];
+        let expected = vec![
+            vec![vec![0, 0, 0], vec![2, 2, 2]],
+            vec![vec![1, 1, 1], vec![1, 1, 1]],
+        ];
+        let dev = get_device();
+        let x = dev.new_tensor_by_slice(shape![3, 3; 2], &x_data);
+        for &i in &[0, 1] {
+            for &b in &[0, 1] {
+                let mut result = vec![0; 3];
+                dev.call_fw_u32_impl("argmin_impl", &[&x], &[i, b], &[], &mut result);
+                assert_eq!(expected[i as usize][b as usize], result);
+            }
+        }
+    }
+
     #[test]
     fn check_argmin_large() {
         let ns = vec![
@@ -2,7 +2,6 @@ use std::sync::Arc;
 use std::sync::Mutex;
 
 use ocl_core::ArgVal;
-use ocl_core::Event;
 use ocl_core::Kernel;
 use ocl_core::KernelWorkGroupInfo;
 use ocl_core::KernelWorkGroupInfoResult;
@@ -48,6 +47,13 @@ impl FunctionFwU32Impl for ArgsortImpl {
         let s = x.shape();
         let skip = s.lower_volume(dim);
         let len = s[dim];
+        // With a second argument, only that batch element is sorted. The indices still
+        // address the whole tensor.
+        let (first, size) = match u32data.get(1) {
+            Some(&batch) => crate::OpenCL::batch_range(&s, batch),
+            None => (0, s.size()),
+        };
+        assert_eq!(size as usize, ys.len());
         let idx_len = {
             let mut idx_len = 1;
             while idx_len < len {
@@ -55,7 +61,6 @@ impl FunctionFwU32Impl for ArgsortImpl {
             }
             idx_len
         };
-        let size = s.size();
         let idx_size = size / len * idx_len;
         let ret = unsafe {
             ocl_core::create_buffer(
@@ -71,18 +76,17 @@ impl FunctionFwU32Impl for ArgsortImpl {
             let kernel = self.init_kernel.lock().unwrap();
             unsafe {
                 ocl_core::set_kernel_arg(&kernel, 0, ArgVal::scalar(&idx_size)).unwrap();
-                ocl_core::set_kernel_arg(&kernel, 1, ArgVal::mem(&ret)).unwrap();
-                ocl_core::enqueue_kernel(
-                    &self.internal.queue,
-                    &kernel,
-                    1,
-                    None,
-                    &[g1 * self.wgs[0], 1, 1],
-                    Some([self.wgs[0], 1, 1]),
-                    None::<Event>,
-                    None::<&mut Event>,
-                )
-                .unwrap();
+                ocl_core::set_kernel_arg(&kernel, 1, ArgVal::scalar(&first)).unwrap();
+                ocl_core::set_kernel_arg(&kernel, 2, ArgVal::mem(&ret)).unwrap();
+                self.internal
+                    .enqueue_kernel(
+                        &kernel,
+                        1,
+                        None,
+                        &[g1 * self.wgs[0], 1, 1],
+                        Some([self.wgs[0], 1, 1]),
+                    )
+                    .unwrap();
             }
         }
         {
@@ -101,18 +105,17 @@ impl FunctionFwU32Impl for ArgsortImpl {
                         ocl_core::set_kernel_arg(&kernel, 5, ArgVal::scalar(&idx_len)).unwrap();
                         ocl_core::set_kernel_arg(&kernel, 6, ArgVal::scalar(&size)).unwrap();
                         ocl_core::set_kernel_arg(&kernel, 7, ArgVal::scalar(&idx_size)).unwrap();
-                        ocl_core::set_kernel_arg(&kernel, 8, ArgVal::mem(&ret)).unwrap();
-                        ocl_core::enqueue_kernel(
-                            &self.internal.queue,
-                            &kernel,
-                            1,
-                            None,
-                            &[g1 * self.wgs[0], 1, 1],
-                            Some([self.wgs[0], 1, 1]),
-                            None::<Event>,
-                            None::<&mut Event>,
-                        )
-                        .unwrap();
+                        ocl_core::set_kernel_arg(&kernel, 8, ArgVal::scalar(&first)).unwrap();
+                        ocl_core::set_kernel_arg(&kernel, 9, ArgVal::mem(&ret)).unwrap();
+                        self.internal
+                            .enqueue_kernel(
+                                &kernel,
+                                1,
+                                None,
+                                &[g1 * self.wgs[0], 1, 1],
+                                Some([self.wgs[0], 1, 1]),
+                            )
+                            .unwrap();
                     }
                     dist >>= 1;
                 }
@@ -120,7 +123,7 @@ impl FunctionFwU32Impl for ArgsortImpl {
             }
         }
         unsafe {
-            super::common::read_buffer(&self.internal.queue, &ret, ys);
+            super::common::read_buffer(&self.internal, &ret, ys);
         }
     }
 }
@@ -161,6 +164,30 @@ mod tests {
         }
     }
 
+    #[test]
+    fn check_argsort_batch() {
+        let x_data = vec![
+            10., 17., 6., 8., 16., 18., 14., 15., 4., 3., 13., 11., 9., 12., 2., 1., 5., 7.,
+        ];
+        let expected = vec![
+            vec![6., 10., 17., 8., 16., 18., 4., 14., 15.],
+            vec![3., 11., 13., 2., 9., 12., 1., 5., 7.],
+        ];
+        let dev = get_device();
+        let x = dev.new_tensor_by_slice(shape![3, 3; 2], &x_data);
+        for &b in &[0, 1] {
+            let mut result = vec![0; 9];
+            dev.call_fw_u32_impl("argsort_impl", &[&x], &[0, b], &[], &mut result);
+            // The indices address the whole tensor.
+            assert!(result.iter().all(|&i| i / 9 == b));
+            let sorted = result
+                .iter()
+                .map(|&i| x_data[i as usize])
+                .collect::<Vec<f32>>();
+            assert_eq!(expected[b as usize], sorted);
+        }
+    }
+
     #[test]
     fn check_index_sort_large() {
         let ns = vec![
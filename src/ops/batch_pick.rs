@@ -1,5 +1,4 @@
 use ocl_core::ArgVal;
-use ocl_core::Event;
 
 use prima_undine::device_impl::FunctionBwImpl;
 use prima_undine::device_impl::FunctionFwImpl;
@@ -25,26 +24,23 @@ impl FunctionFwImpl for BatchPickFwImpl {
             )
             .unwrap()
         };
-        let queue = &self.internal.queue;
         let kernel = self.kernel.lock().unwrap();
         unsafe {
-            super::common::write_buffer(&self.internal.queue, ids, &ids_buf);
+            super::common::write_buffer(&self.internal, ids, &ids_buf);
             ocl_core::set_kernel_arg(&kernel, 0, ArgVal::mem(buffer!(x))).unwrap();
             ocl_core::set_kernel_arg(&kernel, 1, ArgVal::mem(&ids_buf)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 2, ArgVal::scalar(&si)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 3, ArgVal::scalar(&sy)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 4, ArgVal::mem(buffer!(y))).unwrap();
-            ocl_core::enqueue_kernel(
-                &queue,
-                &kernel,
-                2,
-                None,
-                &[g1 * self.wgs[0], bs, 1],
-                Some([self.wgs[0], 1, 1]),
-                None::<Event>,
-                None::<&mut Event>,
-            )
-            .unwrap();
+            self.internal
+                .enqueue_kernel(
+                    &kernel,
+                    2,
+                    None,
+                    &[g1 * self.wgs[0], bs, 1],
+                    Some([self.wgs[0], 1, 1]),
+                )
+                .unwrap();
         }
     }
 }
@@ -75,26 +71,23 @@ impl FunctionBwImpl for BatchPickBwImpl {
             )
             .unwrap()
         };
-        let queue = &self.internal.queue;
         let kernel = self.kernel.lock().unwrap();
         unsafe {
-            super::common::write_buffer(&self.internal.queue, ids, &ids_buf);
+            super::common::write_buffer(&self.internal, ids, &ids_buf);
             ocl_core::set_kernel_arg(&kernel, 0, ArgVal::mem(buffer!(gy))).unwrap();
             ocl_core::set_kernel_arg(&kernel, 1, ArgVal::mem(&ids_buf)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 2, ArgVal::scalar(&si)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 3, ArgVal::scalar(&sy)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 4, ArgVal::mem(buffer!(gx))).unwrap();
-            ocl_core::enqueue_kernel(
-                &queue,
-                &kernel,
-                2,
-                None,
-                &[g1 * self.wgs[0], bs, 1],
-                Some([self.wgs[0], 1, 1]),
-                None::<Event>,
-                None::<&mut Event>,
-            )
-            .unwrap();
+            self.internal
+                .enqueue_kernel(
+                    &kernel,
+                    2,
+                    None,
+                    &[g1 * self.wgs[0], bs, 1],
+                    Some([self.wgs[0], 1, 1]),
+                )
+                .unwrap();
         }
     }
 }
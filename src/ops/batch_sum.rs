@@ -1,5 +1,4 @@
 use ocl_core::ArgVal;
-use ocl_core::Event;
 
 use prima_undine::device_impl::FunctionFwImpl;
 use prima_undine::functions::BasicFunctions;
@@ -13,24 +12,21 @@ impl FunctionFwImpl for BatchSumFwImpl {
         let size = y.shape().size();
         let batch = x.shape().batch();
         let g1 = super::common::calc_num_blocks(size as usize, self.wgs[0]);
-        let queue = &self.internal.queue;
         let kernel = self.kernel.lock().unwrap();
         unsafe {
             ocl_core::set_kernel_arg(&kernel, 0, ArgVal::mem(buffer!(x))).unwrap();
             ocl_core::set_kernel_arg(&kernel, 1, ArgVal::scalar(&size)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 2, ArgVal::scalar(&batch)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 3, ArgVal::mem(buffer!(y))).unwrap();
-            ocl_core::enqueue_kernel(
-                &queue,
-                &kernel,
-                1,
-                None,
-                &[g1 * self.wgs[0], 1, 1],
-                Some([self.wgs[0], 1, 1]),
-                None::<Event>,
-                None::<&mut Event>,
-            )
-            .unwrap();
+            self.internal
+                .enqueue_kernel(
+                    &kernel,
+                    1,
+                    None,
+                    &[g1 * self.wgs[0], 1, 1],
+                    Some([self.wgs[0], 1, 1]),
+                )
+                .unwrap();
         }
     }
 }
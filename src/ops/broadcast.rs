@@ -1,5 +1,4 @@
 use ocl_core::ArgVal;
-use ocl_core::Event;
 
 use prima_undine::device_impl::FunctionFwImpl;
 use prima_undine::functions::BasicFunctions;
@@ -16,7 +15,6 @@ impl FunctionFwImpl for BroadcastFwImpl {
         let skip2 = skip1 * size;
         let total = y.shape().size();
         let g1 = super::common::calc_num_blocks(total as usize, self.wgs[0]);
-        let queue = &self.internal.queue;
         let kernel = self.kernel.lock().unwrap();
         unsafe {
             ocl_core::set_kernel_arg(&kernel, 0, ArgVal::mem(buffer!(x))).unwrap();
@@ -24,17 +22,15 @@ impl FunctionFwImpl for BroadcastFwImpl {
             ocl_core::set_kernel_arg(&kernel, 2, ArgVal::scalar(&skip2)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 3, ArgVal::scalar(&total)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 4, ArgVal::mem(buffer!(y))).unwrap();
-            ocl_core::enqueue_kernel(
-                &queue,
-                &kernel,
-                1,
-                None,
-                &[g1 * self.wgs[0], 1, 1],
-                Some([self.wgs[0], 1, 1]),
-                None::<Event>,
-                None::<&mut Event>,
-            )
-            .unwrap();
+            self.internal
+                .enqueue_kernel(
+                    &kernel,
+                    1,
+                    None,
+                    &[g1 * self.wgs[0], 1, 1],
+                    Some([self.wgs[0], 1, 1]),
+                )
+                .unwrap();
         }
     }
 }
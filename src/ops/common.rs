@@ -1,44 +1,80 @@
-use ocl_core::CommandQueue;
-use ocl_core::Event;
 use ocl_core::MapFlags;
 use ocl_core::Mem;
 use ocl_core::OclPrm;
 
+use crate::tracer::Command;
+use crate::OpenCLInternal;
+
 pub fn calc_num_blocks(size: usize, num_threads: usize) -> usize {
     (size + num_threads - 1) / num_threads
 }
 
-pub unsafe fn read_buffer<T: OclPrm>(queue: &CommandQueue, buf: &Mem, ret: &mut [T]) {
-    let mem = ocl_core::enqueue_map_buffer(
-        &queue,
-        buf,
-        true,
-        MapFlags::READ,
-        0,
-        ret.len(),
-        None::<Event>,
-        None::<&mut Event>,
-    )
-    .unwrap();
+pub unsafe fn read_buffer<T: OclPrm>(internal: &OpenCLInternal, buf: &Mem, ret: &mut [T]) {
+    read_buffer_range(internal, buf, 0, ret);
+}
+
+pub unsafe fn read_buffer_range<T: OclPrm>(
+    internal: &OpenCLInternal,
+    buf: &Mem,
+    offset: usize,
+    ret: &mut [T],
+) {
+    let mem = internal
+        .with_event(Command::MapBuffer, |wait_list, event| {
+            ocl_core::enqueue_map_buffer(
+                internal.queue(),
+                buf,
+                true,
+                MapFlags::READ,
+                offset,
+                ret.len(),
+                wait_list,
+                event,
+            )
+        })
+        .unwrap();
     ret.clone_from_slice(mem.as_slice(ret.len()));
-    ocl_core::enqueue_unmap_mem_object(&queue, buf, &mem, None::<Event>, None::<&mut Event>)
+    internal
+        .with_event(Command::UnmapBuffer, |wait_list, event| {
+            ocl_core::enqueue_unmap_mem_object(internal.queue(), buf, &mem, wait_list, event)
+        })
         .unwrap();
 }
 
-pub unsafe fn write_buffer<T: OclPrm>(queue: &CommandQueue, val: &[T], buf: &Mem) {
-    let mut mem = ocl_core::enqueue_map_buffer(
-        &queue,
-        buf,
-        true,
-        MapFlags::WRITE,
-        0,
-        val.len(),
-        None::<Event>,
-        None::<&mut Event>,
-    )
-    .unwrap();
+pub unsafe fn write_buffer<T: OclPrm>(internal: &OpenCLInternal, val: &[T], buf: &Mem) {
+    write_buffer_range(internal, val, buf, 0);
+}
+
+pub unsafe fn write_buffer_range<T: OclPrm>(
+    internal: &OpenCLInternal,
+    val: &[T],
+    buf: &Mem,
+    offset: usize,
+) {
+    let mut mem = internal
+        .with_event(Command::MapBuffer, |wait_list, event| {
+            ocl_core::enqueue_map_buffer(
+                internal.queue(),
+                buf,
+                true,
+                // Host-backed buffers are overwritten in place without reading them back.
+                if internal.zero_copy {
+                    MapFlags::WRITE_INVALIDATE_REGION
+                } else {
+                    MapFlags::WRITE
+                },
+                offset,
+                val.len(),
+                wait_list,
+                event,
+            )
+        })
+        .unwrap();
     mem.as_slice_mut(val.len()).clone_from_slice(val);
-    ocl_core::enqueue_unmap_mem_object(&queue, buf, &mem, None::<Event>, None::<&mut Event>)
+    internal
+        .with_event(Command::UnmapBuffer, |wait_list, event| {
+            ocl_core::enqueue_unmap_mem_object(internal.queue(), buf, &mem, wait_list, event)
+        })
         .unwrap();
 }
 
@@ -120,17 +156,15 @@ macro_rules! define_opencl_fw_x_impl {
                     ocl_core::set_kernel_arg(&kernel, 1, ocl_core::ArgVal::scalar(&size)).unwrap();
                     ocl_core::set_kernel_arg(&kernel, 2, ocl_core::ArgVal::mem(buffer!(y)))
                         .unwrap();
-                    ocl_core::enqueue_kernel(
-                        &self.internal.queue,
-                        &kernel,
-                        1,
-                        None,
-                        &[g1 * self.wgs[0], 1, 1],
-                        Some([self.wgs[0], 1, 1]),
-                        None::<ocl_core::Event>,
-                        None::<&mut ocl_core::Event>,
-                    )
-                    .unwrap();
+                    self.internal
+                        .enqueue_kernel(
+                            &kernel,
+                            1,
+                            None,
+                            &[g1 * self.wgs[0], 1, 1],
+                            Some([self.wgs[0], 1, 1]),
+                        )
+                        .unwrap();
                 }
             }
         }
@@ -167,17 +201,15 @@ macro_rules! define_opencl_bw_x_impl {
                     ocl_core::set_kernel_arg(&kernel, 3, ocl_core::ArgVal::scalar(&size)).unwrap();
                     ocl_core::set_kernel_arg(&kernel, 4, ocl_core::ArgVal::mem(buffer!(gx)))
                         .unwrap();
-                    ocl_core::enqueue_kernel(
-                        &self.internal.queue,
-                        &kernel,
-                        1,
-                        None,
-                        &[g1 * self.wgs[0], 1, 1],
-                        Some([self.wgs[0], 1, 1]),
-                        None::<ocl_core::Event>,
-                        None::<&mut ocl_core::Event>,
-                    )
-                    .unwrap();
+                    self.internal
+                        .enqueue_kernel(
+                            &kernel,
+                            1,
+                            None,
+                            &[g1 * self.wgs[0], 1, 1],
+                            Some([self.wgs[0], 1, 1]),
+                        )
+                        .unwrap();
                 }
             }
         }
@@ -215,17 +247,15 @@ macro_rules! define_opencl_fw_ab_impl {
                     ocl_core::set_kernel_arg(&kernel, 4, ocl_core::ArgVal::scalar(&mbb)).unwrap();
                     ocl_core::set_kernel_arg(&kernel, 5, ocl_core::ArgVal::mem(buffer!(y)))
                         .unwrap();
-                    ocl_core::enqueue_kernel(
-                        &self.internal.queue,
-                        &kernel,
-                        2,
-                        None,
-                        &[g1 * self.wgs[0], g2, 1],
-                        Some([self.wgs[0], 1, 1]),
-                        None::<ocl_core::Event>,
-                        None::<&mut ocl_core::Event>,
-                    )
-                    .unwrap();
+                    self.internal
+                        .enqueue_kernel(
+                            &kernel,
+                            2,
+                            None,
+                            &[g1 * self.wgs[0], g2, 1],
+                            Some([self.wgs[0], 1, 1]),
+                        )
+                        .unwrap();
                 }
             }
         }
@@ -271,17 +301,15 @@ macro_rules! define_opencl_bw_a_impl {
                     ocl_core::set_kernel_arg(&kernel, 6, ocl_core::ArgVal::scalar(&mbb)).unwrap();
                     ocl_core::set_kernel_arg(&kernel, 7, ocl_core::ArgVal::mem(buffer!(ga)))
                         .unwrap();
-                    ocl_core::enqueue_kernel(
-                        &self.internal.queue,
-                        &kernel,
-                        2,
-                        None,
-                        &[g1 * self.wgs[0], g2, 1],
-                        Some([self.wgs[0], 1, 1]),
-                        None::<ocl_core::Event>,
-                        None::<&mut ocl_core::Event>,
-                    )
-                    .unwrap();
+                    self.internal
+                        .enqueue_kernel(
+                            &kernel,
+                            2,
+                            None,
+                            &[g1 * self.wgs[0], g2, 1],
+                            Some([self.wgs[0], 1, 1]),
+                        )
+                        .unwrap();
                 }
             }
         }
@@ -327,17 +355,15 @@ macro_rules! define_opencl_bw_b_impl {
                     ocl_core::set_kernel_arg(&*kernel, 6, ocl_core::ArgVal::scalar(&mbb)).unwrap();
                     ocl_core::set_kernel_arg(&*kernel, 7, ocl_core::ArgVal::mem(buffer!(gb)))
                         .unwrap();
-                    ocl_core::enqueue_kernel(
-                        &self.internal.queue,
-                        &kernel,
-                        2,
-                        None,
-                        &[g1 * self.wgs[0], g2, 1],
-                        Some([self.wgs[0], 1, 1]),
-                        None::<ocl_core::Event>,
-                        None::<&mut ocl_core::Event>,
-                    )
-                    .unwrap();
+                    self.internal
+                        .enqueue_kernel(
+                            &kernel,
+                            2,
+                            None,
+                            &[g1 * self.wgs[0], g2, 1],
+                            Some([self.wgs[0], 1, 1]),
+                        )
+                        .unwrap();
                 }
             }
         }
@@ -369,17 +395,15 @@ macro_rules! define_opencl_fw_const_impl {
                     ocl_core::set_kernel_arg(&kernel, 2, ocl_core::ArgVal::scalar(&size)).unwrap();
                     ocl_core::set_kernel_arg(&kernel, 3, ocl_core::ArgVal::mem(buffer!(y)))
                         .unwrap();
-                    ocl_core::enqueue_kernel(
-                        &self.internal.queue,
-                        &kernel,
-                        1,
-                        None,
-                        &[g1 * self.wgs[0], 1, 1],
-                        Some([self.wgs[0], 1, 1]),
-                        None::<ocl_core::Event>,
-                        None::<&mut ocl_core::Event>,
-                    )
-                    .unwrap();
+                    self.internal
+                        .enqueue_kernel(
+                            &kernel,
+                            1,
+                            None,
+                            &[g1 * self.wgs[0], 1, 1],
+                            Some([self.wgs[0], 1, 1]),
+                        )
+                        .unwrap();
                 }
             }
         }
@@ -418,17 +442,15 @@ macro_rules! define_opencl_bw_const_impl {
                     ocl_core::set_kernel_arg(&kernel, 4, ocl_core::ArgVal::scalar(&size)).unwrap();
                     ocl_core::set_kernel_arg(&kernel, 5, ocl_core::ArgVal::mem(buffer!(gx)))
                         .unwrap();
-                    ocl_core::enqueue_kernel(
-                        &self.internal.queue,
-                        &kernel,
-                        1,
-                        None,
-                        &[g1 * self.wgs[0], 1, 1],
-                        Some([self.wgs[0], 1, 1]),
-                        None::<ocl_core::Event>,
-                        None::<&mut ocl_core::Event>,
-                    )
-                    .unwrap();
+                    self.internal
+                        .enqueue_kernel(
+                            &kernel,
+                            1,
+                            None,
+                            &[g1 * self.wgs[0], 1, 1],
+                            Some([self.wgs[0], 1, 1]),
+                        )
+                        .unwrap();
                 }
             }
         }
@@ -1,5 +1,4 @@
 use ocl_core::ArgVal;
-use ocl_core::Event;
 
 use prima_undine::device_impl::FunctionFwImpl;
 use prima_undine::functions::BasicFunctions;
@@ -20,7 +19,6 @@ impl FunctionFwImpl for ConcatFwImpl {
             let x_size = span * repeat * x.shape().batch();
             let y_size = span * repeat * new_bs;
             let g1 = super::common::calc_num_blocks(y_size as usize, self.wgs[0]);
-            let queue = &self.internal.queue;
             let kernel = self.kernel.lock().unwrap();
             unsafe {
                 ocl_core::set_kernel_arg(&kernel, 0, ArgVal::mem(buffer!(x))).unwrap();
@@ -30,17 +28,15 @@ impl FunctionFwImpl for ConcatFwImpl {
                 ocl_core::set_kernel_arg(&kernel, 4, ArgVal::scalar(&y_size)).unwrap();
                 ocl_core::set_kernel_arg(&kernel, 5, ArgVal::mem(buffer!(y))).unwrap();
                 ocl_core::set_kernel_arg(&kernel, 6, ArgVal::scalar(&offset)).unwrap();
-                ocl_core::enqueue_kernel(
-                    &queue,
-                    &kernel,
-                    1,
-                    None,
-                    &[g1 * self.wgs[0], 1, 1],
-                    Some([self.wgs[0], 1, 1]),
-                    None::<Event>,
-                    None::<&mut Event>,
-                )
-                .unwrap();
+                self.internal
+                    .enqueue_kernel(
+                        &kernel,
+                        1,
+                        None,
+                        &[g1 * self.wgs[0], 1, 1],
+                        Some([self.wgs[0], 1, 1]),
+                    )
+                    .unwrap();
             }
             offset += span;
         }
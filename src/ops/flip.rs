@@ -1,5 +1,4 @@
 use ocl_core::ArgVal;
-use ocl_core::Event;
 
 use prima_undine::device_impl::FunctionBwImpl;
 use prima_undine::device_impl::FunctionFwImpl;
@@ -17,7 +16,6 @@ impl FunctionFwImpl for FlipFwImpl {
         let r = x.shape().size() / n;
         let g1 = super::common::calc_num_blocks(n as usize, self.wgs[0]);
         let g2 = super::common::calc_num_blocks(r as usize, self.wgs[1]);
-        let queue = &self.internal.queue;
         let kernel = self.kernel.lock().unwrap();
         unsafe {
             ocl_core::set_kernel_arg(&kernel, 0, ArgVal::mem(buffer!(x))).unwrap();
@@ -25,17 +23,15 @@ impl FunctionFwImpl for FlipFwImpl {
             ocl_core::set_kernel_arg(&kernel, 2, ArgVal::scalar(&n)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 3, ArgVal::scalar(&r)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 4, ArgVal::mem(buffer!(y))).unwrap();
-            ocl_core::enqueue_kernel(
-                &queue,
-                &kernel,
-                2,
-                None,
-                &[g1 * self.wgs[0], g2 * self.wgs[1], 1],
-                Some([self.wgs[0], self.wgs[1], 1]),
-                None::<Event>,
-                None::<&mut Event>,
-            )
-            .unwrap();
+            self.internal
+                .enqueue_kernel(
+                    &kernel,
+                    2,
+                    None,
+                    &[g1 * self.wgs[0], g2 * self.wgs[1], 1],
+                    Some([self.wgs[0], self.wgs[1], 1]),
+                )
+                .unwrap();
         }
     }
 }
@@ -58,7 +54,6 @@ impl FunctionBwImpl for FlipBwImpl {
         let r = gx.shape().size() / n;
         let g1 = super::common::calc_num_blocks(n as usize, self.wgs[0]);
         let g2 = super::common::calc_num_blocks(r as usize, self.wgs[1]);
-        let queue = &self.internal.queue;
         let kernel = self.kernel.lock().unwrap();
         unsafe {
             ocl_core::set_kernel_arg(&kernel, 0, ArgVal::mem(buffer!(gy))).unwrap();
@@ -66,17 +61,15 @@ impl FunctionBwImpl for FlipBwImpl {
             ocl_core::set_kernel_arg(&kernel, 2, ArgVal::scalar(&n)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 3, ArgVal::scalar(&r)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 4, ArgVal::mem(buffer!(gx))).unwrap();
-            ocl_core::enqueue_kernel(
-                &queue,
-                &kernel,
-                2,
-                None,
-                &[g1 * self.wgs[0], g2 * self.wgs[1], 1],
-                Some([self.wgs[0], self.wgs[1], 1]),
-                None::<Event>,
-                None::<&mut Event>,
-            )
-            .unwrap();
+            self.internal
+                .enqueue_kernel(
+                    &kernel,
+                    2,
+                    None,
+                    &[g1 * self.wgs[0], g2 * self.wgs[1], 1],
+                    Some([self.wgs[0], self.wgs[1], 1]),
+                )
+                .unwrap();
         }
     }
 }
@@ -1,290 +1,276 @@
-use std::ptr;
+use std::sync::Arc;
 
 use prima_undine::device_impl::FunctionBwImpl;
 use prima_undine::device_impl::FunctionFwImpl;
 use prima_undine::functions::BasicFunctions;
 use prima_undine::Tensor;
 
-use crate::clblast;
+use crate::gemm::{Gemm, Operand, Sgemm};
+use crate::OpenCLInternal;
 
-define_empty_impl!(MatmulFwImpl);
-impl FunctionFwImpl for MatmulFwImpl {
-    fn call(&self, xs: &[&Tensor], _u32data: &[u32], _f32data: &[f32], ys: &mut [&mut Tensor]) {
-        let a = xs[0];
-        let b = xs[1];
-        let y = &mut ys[0];
-        let di = a.shape()[0] as usize;
-        let dj = a.shape()[1] as usize;
-        let dk = b.shape()[1] as usize;
-        if a.shape().has_batch() {
-            let a_skip = di * dj;
-            let b_skip = if b.shape().has_batch() { dj * dk } else { 0 };
-            let y_skip = di * dk;
-            let bs = a.shape().batch() as usize;
-            let alphas = vec![1.; bs];
-            let betas = vec![0.; bs];
-            let mut a_offsets = vec![0; bs];
-            let mut b_offsets = vec![0; bs];
-            let mut y_offsets = vec![0; bs];
-            for n in 0..bs {
-                a_offsets[n] = n * a_skip;
-                b_offsets[n] = n * b_skip;
-                y_offsets[n] = n * y_skip;
+macro_rules! define_gemm_impl {
+    ( $name:ident ) => {
+        pub struct $name {
+            gemm: Gemm,
+        }
+        impl $name {
+            pub fn new(internal: &Arc<OpenCLInternal>) -> $name {
+                $name {
+                    gemm: Gemm::new(internal),
+                }
+            }
+        }
+    };
+}
+
+// Defines the forward and backward impls of `op(a) * op(b)`, where `op`
+// transposes its operand if the corresponding flag is set.
+macro_rules! define_matmul_impls {
+    ( $fw:ident, $bw_a:ident, $bw_b:ident, $trans_a:expr, $trans_b:expr ) => {
+        define_gemm_impl!($fw);
+        impl FunctionFwImpl for $fw {
+            fn call(
+                &self,
+                xs: &[&Tensor],
+                _u32data: &[u32],
+                _f32data: &[f32],
+                ys: &mut [&mut Tensor],
+            ) {
+                matmul_fw(&self.gemm, $trans_a, $trans_b, xs[0], xs[1], ys[0]);
             }
-            unsafe {
-                clblast::CLBlastSgemmBatched(
-                    clblast::layout::COL_MAJOR,
-                    clblast::transpose::NO,
-                    clblast::transpose::NO,
-                    di,
-                    dk,
-                    dj,
-                    alphas.as_ptr(),
-                    buffer!(a).as_ptr(),
-                    a_offsets.as_ptr(),
-                    di,
-                    buffer!(b).as_ptr(),
-                    b_offsets.as_ptr(),
-                    dj,
-                    betas.as_ptr(),
-                    buffer!(y).as_ptr(),
-                    y_offsets.as_ptr(),
-                    di,
-                    bs,
-                    &mut self.internal.queue.as_ptr(),
-                    ptr::null_mut(),
-                );
+        }
+
+        define_gemm_impl!($bw_a);
+        impl FunctionBwImpl for $bw_a {
+            fn call(
+                &self,
+                xs: &[&Tensor],
+                _ys: &[&Tensor],
+                gys: &[&Tensor],
+                _u32data: &[u32],
+                _f32data: &[f32],
+                gx: &mut Tensor,
+            ) {
+                matmul_bw_a(&self.gemm, $trans_a, $trans_b, xs[0], xs[1], gys[0], gx);
+            }
+        }
+
+        define_gemm_impl!($bw_b);
+        impl FunctionBwImpl for $bw_b {
+            fn call(
+                &self,
+                xs: &[&Tensor],
+                _ys: &[&Tensor],
+                gys: &[&Tensor],
+                _u32data: &[u32],
+                _f32data: &[f32],
+                gx: &mut Tensor,
+            ) {
+                matmul_bw_b(&self.gemm, $trans_a, $trans_b, xs[0], xs[1], gys[0], gx);
             }
+        }
+    };
+}
+
+define_matmul_impls!(MatmulFwImpl, MatmulBwAImpl, MatmulBwBImpl, false, false);
+define_matmul_impls!(
+    MatmulTnFwImpl,
+    MatmulTnBwAImpl,
+    MatmulTnBwBImpl,
+    true,
+    false
+);
+define_matmul_impls!(
+    MatmulNtFwImpl,
+    MatmulNtBwAImpl,
+    MatmulNtBwBImpl,
+    false,
+    true
+);
+define_matmul_impls!(MatmulTtFwImpl, MatmulTtBwAImpl, MatmulTtBwBImpl, true, true);
+
+// Sizes of `op(a) * op(b)`: `op(a)` is `m x k` and `op(b)` is `k x n`.
+struct Dims {
+    m: usize,
+    n: usize,
+    k: usize,
+    lda: usize,
+    ldb: usize,
+    a_skip: usize,
+    b_skip: usize,
+}
+
+impl Dims {
+    fn new(a: &Tensor, b: &Tensor, trans_a: bool, trans_b: bool) -> Dims {
+        let lda = a.shape()[0] as usize;
+        let ldb = b.shape()[0] as usize;
+        let (m, k) = if trans_a {
+            (a.shape()[1] as usize, lda)
         } else {
-            let alpha = 1.;
-            let beta = 0.;
-            unsafe {
-                clblast::CLBlastSgemm(
-                    clblast::layout::COL_MAJOR,
-                    clblast::transpose::NO,
-                    clblast::transpose::NO,
-                    di,
-                    dk * b.shape().batch() as usize,
-                    dj,
-                    alpha,
-                    buffer!(a).as_ptr(),
-                    0,
-                    di,
-                    buffer!(b).as_ptr(),
-                    0,
-                    dj,
-                    beta,
-                    buffer!(y).as_ptr(),
-                    0,
-                    di,
-                    &mut self.internal.queue.as_ptr(),
-                    ptr::null_mut(),
-                );
+            (lda, a.shape()[1] as usize)
+        };
+        let n = if trans_b { ldb } else { b.shape()[1] as usize };
+        let skip = |x: &Tensor| {
+            if x.shape().has_batch() {
+                x.shape().volume() as usize
+            } else {
+                0
             }
+        };
+        Dims {
+            m,
+            n,
+            k,
+            lda,
+            ldb,
+            a_skip: skip(a),
+            b_skip: skip(b),
         }
     }
 }
 
-define_empty_impl!(MatmulBwAImpl);
-impl FunctionBwImpl for MatmulBwAImpl {
-    fn call(
-        &self,
-        xs: &[&Tensor],
-        _ys: &[&Tensor],
-        gys: &[&Tensor],
-        _u32data: &[u32],
-        _f32data: &[f32],
-        gx: &mut Tensor,
-    ) {
-        let a = xs[0];
-        let b = xs[1];
-        let gy = gys[0];
-        let ga = gx;
-        let di = a.shape()[0] as usize;
-        let dj = a.shape()[1] as usize;
-        let dk = b.shape()[1] as usize;
-        if a.shape().has_batch() {
-            let a_skip = di * dj;
-            let b_skip = if b.shape().has_batch() { dj * dk } else { 0 };
-            let y_skip = di * dk;
-            let bs = a.shape().batch() as usize;
-            let alphas = vec![1.; bs];
-            let betas = vec![1.; bs];
-            let mut a_offsets = vec![0; bs];
-            let mut b_offsets = vec![0; bs];
-            let mut y_offsets = vec![0; bs];
-            for n in 0..bs {
-                a_offsets[n] = n * a_skip;
-                b_offsets[n] = n * b_skip;
-                y_offsets[n] = n * y_skip;
-            }
-            unsafe {
-                clblast::CLBlastSgemmBatched(
-                    clblast::layout::COL_MAJOR,
-                    clblast::transpose::NO,
-                    clblast::transpose::YES,
-                    di,
-                    dj,
-                    dk,
-                    alphas.as_ptr(),
-                    buffer!(gy).as_ptr(),
-                    y_offsets.as_ptr(),
-                    di,
-                    buffer!(b).as_ptr(),
-                    b_offsets.as_ptr(),
-                    dj,
-                    betas.as_ptr(),
-                    buffer!(ga).as_ptr(),
-                    a_offsets.as_ptr(),
-                    di,
-                    bs,
-                    &mut self.internal.queue.as_ptr(),
-                    ptr::null_mut(),
-                );
+fn matmul_fw(gemm: &Gemm, trans_a: bool, trans_b: bool, a: &Tensor, b: &Tensor, y: &mut Tensor) {
+    let d = Dims::new(a, b, trans_a, trans_b);
+    let bs = y.shape().batch() as usize;
+    unsafe {
+        let p = Sgemm {
+            trans_a,
+            trans_b,
+            m: d.m,
+            n: d.n,
+            k: d.k,
+            alpha: 1.,
+            a: Operand::new(buffer!(a), d.lda, d.a_skip),
+            b: Operand::new(buffer!(b), d.ldb, d.b_skip),
+            beta: 0.,
+            c: Operand::new(buffer!(y), d.m, d.m * d.n),
+            batch: bs,
+        };
+        if d.a_skip == 0 && !trans_b {
+            // The batch elements of `b` and `y` are consecutive columns.
+            gemm.call(&Sgemm {
+                n: d.n * bs,
+                batch: 1,
+                ..p
+            });
+        } else {
+            gemm.call(&p);
+        }
+    }
+}
+
+fn matmul_bw_a(
+    gemm: &Gemm,
+    trans_a: bool,
+    trans_b: bool,
+    a: &Tensor,
+    b: &Tensor,
+    gy: &Tensor,
+    ga: &mut Tensor,
+) {
+    let d = Dims::new(a, b, trans_a, trans_b);
+    let bs = gy.shape().batch() as usize;
+    unsafe {
+        let gy = Operand::new(buffer!(gy), d.m, d.m * d.n);
+        let b = Operand::new(buffer!(b), d.ldb, d.b_skip);
+        let c = Operand::new(buffer!(ga), d.lda, d.a_skip);
+        // gA += gY * op(b)^T, or op(b) * gY^T if `a` is transposed.
+        let p = if trans_a {
+            Sgemm {
+                trans_a: trans_b,
+                trans_b: true,
+                m: d.k,
+                n: d.m,
+                k: d.n,
+                alpha: 1.,
+                a: b,
+                b: gy,
+                beta: 1.,
+                c,
+                batch: bs,
             }
         } else {
-            let alpha = 1.;
-            let beta = 1.;
-            unsafe {
-                clblast::CLBlastSgemm(
-                    clblast::layout::COL_MAJOR,
-                    clblast::transpose::NO,
-                    clblast::transpose::YES,
-                    di,
-                    dj,
-                    dk * b.shape().batch() as usize,
-                    alpha,
-                    buffer!(gy).as_ptr(),
-                    0,
-                    di,
-                    buffer!(b).as_ptr(),
-                    0,
-                    dj,
-                    beta,
-                    buffer!(ga).as_ptr(),
-                    0,
-                    di,
-                    &mut self.internal.queue.as_ptr(),
-                    ptr::null_mut(),
-                );
+            Sgemm {
+                trans_a: false,
+                trans_b: !trans_b,
+                m: d.m,
+                n: d.k,
+                k: d.n,
+                alpha: 1.,
+                a: gy,
+                b,
+                beta: 1.,
+                c,
+                batch: bs,
             }
+        };
+        if d.a_skip == 0 && !trans_b {
+            // The batch elements of `b` and `gy` are consecutive columns.
+            gemm.call(&Sgemm {
+                k: d.n * bs,
+                batch: 1,
+                ..p
+            });
+        } else {
+            gemm.call(&p);
         }
     }
 }
 
-define_empty_impl!(MatmulBwBImpl);
-impl FunctionBwImpl for MatmulBwBImpl {
-    fn call(
-        &self,
-        xs: &[&Tensor],
-        _ys: &[&Tensor],
-        gys: &[&Tensor],
-        _u32data: &[u32],
-        _f32data: &[f32],
-        gx: &mut Tensor,
-    ) {
-        let a = xs[0];
-        let b = xs[1];
-        let gy = gys[0];
-        let gb = gx;
-        let di = a.shape()[0] as usize;
-        let dj = a.shape()[1] as usize;
-        let dk = b.shape()[1] as usize;
-        if a.shape().has_batch() {
-            let a_skip = di * dj;
-            let b_skip = if b.shape().has_batch() { dj * dk } else { 0 };
-            let y_skip = di * dk;
-            let bs = a.shape().batch() as usize;
-            let alphas = vec![1.; bs];
-            let betas = vec![1.; bs];
-            let mut a_offsets = vec![0; bs];
-            let mut b_offsets = vec![0; bs];
-            let mut y_offsets = vec![0; bs];
-            for n in 0..bs {
-                a_offsets[n] = n * a_skip;
-                b_offsets[n] = n * b_skip;
-                y_offsets[n] = n * y_skip;
-            }
-            if b_skip > 0 {
-                unsafe {
-                    clblast::CLBlastSgemmBatched(
-                        clblast::layout::COL_MAJOR,
-                        clblast::transpose::YES,
-                        clblast::transpose::NO,
-                        dj,
-                        dk,
-                        di,
-                        alphas.as_ptr(),
-                        buffer!(a).as_ptr(),
-                        a_offsets.as_ptr(),
-                        di,
-                        buffer!(gy).as_ptr(),
-                        y_offsets.as_ptr(),
-                        di,
-                        betas.as_ptr(),
-                        buffer!(gb).as_ptr(),
-                        b_offsets.as_ptr(),
-                        dj,
-                        bs,
-                        &mut self.internal.queue.as_ptr(),
-                        ptr::null_mut(),
-                    );
-                }
-            } else {
-                let alpha = 1.;
-                let beta = 1.;
-                for n in 0..bs {
-                    unsafe {
-                        clblast::CLBlastSgemm(
-                            clblast::layout::COL_MAJOR,
-                            clblast::transpose::YES,
-                            clblast::transpose::NO,
-                            dj,
-                            dk,
-                            di,
-                            alpha,
-                            buffer!(a).as_ptr(),
-                            n * a_skip,
-                            di,
-                            buffer!(gy).as_ptr(),
-                            n * y_skip,
-                            di,
-                            beta,
-                            buffer!(gb).as_ptr(),
-                            n * b_skip,
-                            dj,
-                            &mut self.internal.queue.as_ptr(),
-                            ptr::null_mut(),
-                        );
-                    }
-                }
+fn matmul_bw_b(
+    gemm: &Gemm,
+    trans_a: bool,
+    trans_b: bool,
+    a: &Tensor,
+    b: &Tensor,
+    gy: &Tensor,
+    gb: &mut Tensor,
+) {
+    let d = Dims::new(a, b, trans_a, trans_b);
+    let bs = gy.shape().batch() as usize;
+    unsafe {
+        let a = Operand::new(buffer!(a), d.lda, d.a_skip);
+        let gy = Operand::new(buffer!(gy), d.m, d.m * d.n);
+        let c = Operand::new(buffer!(gb), d.ldb, d.b_skip);
+        // gB += op(a)^T * gY, or gY^T * op(a) if `b` is transposed.
+        let p = if trans_b {
+            Sgemm {
+                trans_a: true,
+                trans_b: trans_a,
+                m: d.n,
+                n: d.k,
+                k: d.m,
+                alpha: 1.,
+                a: gy,
+                b: a,
+                beta: 1.,
+                c,
+                batch: bs,
             }
         } else {
-            let alpha = 1.;
-            let beta = 1.;
-            unsafe {
-                clblast::CLBlastSgemm(
-                    clblast::layout::COL_MAJOR,
-                    clblast::transpose::YES,
-                    clblast::transpose::NO,
-                    dj,
-                    dk * b.shape().batch() as usize,
-                    di,
-                    alpha,
-                    buffer!(a).as_ptr(),
-                    0,
-                    di,
-                    buffer!(gy).as_ptr(),
-                    0,
-                    di,
-                    beta,
-                    buffer!(gb).as_ptr(),
-                    0,
-                    dj,
-                    &mut self.internal.queue.as_ptr(),
-                    ptr::null_mut(),
-                );
+            Sgemm {
+                trans_a: !trans_a,
+                trans_b: false,
+                m: d.k,
+                n: d.n,
+                k: d.m,
+                alpha: 1.,
+                a,
+                b: gy,
+                beta: 1.,
+                c,
+                batch: bs,
             }
+        };
+        if d.a_skip == 0 && !trans_b {
+            // The batch elements of `gy` and `gb` are consecutive columns.
+            gemm.call(&Sgemm {
+                n: d.n * bs,
+                batch: 1,
+                ..p
+            });
+        } else {
+            gemm.call(&p);
         }
     }
 }
@@ -407,6 +393,18 @@ mod tests {
         assert_vector_ulps_eq!(y2_data, y2.to_vec());
     }
 
+    #[test]
+    #[should_panic(
+        expected = "matmul_fw_impl: buffer of 256 bytes is too small for matrix C of 40000 bytes"
+    )]
+    fn check_matmul_output_too_small() {
+        let dev = crate::OpenCL::from_env().unwrap();
+        let a = dev.new_tensor_by_constant(shape![100, 100], 1.);
+        let mut y = dev.new_tensor(shape![2, 2]);
+        y.alloc();
+        dev.call_fw_impl("matmul_fw_impl", &[&a, &a], &[], &[], &mut [&mut y]);
+    }
+
     #[test]
     fn check_matmul_bw_11() {
         let a_data = vec![1., 2., 3., 4.];
@@ -646,4 +644,137 @@ mod tests {
         assert_vector_ulps_eq!(ga_data, ga.to_vec());
         assert_vector_ulps_eq!(gb_data, gb.to_vec());
     }
+
+    // Transposes each batch element of a column-major `rows x cols` matrix.
+    fn transpose(data: &[f32], rows: u32, cols: u32) -> Vec<f32> {
+        let (rows, cols) = (rows as usize, cols as usize);
+        let mut ret = vec![0.; data.len()];
+        for (n, x) in data.chunks(rows * cols).enumerate() {
+            for i in 0..rows {
+                for j in 0..cols {
+                    ret[n * rows * cols + j + i * cols] = x[i + j * rows];
+                }
+            }
+        }
+        ret
+    }
+
+    // Compares the transposed variants against `matmul_*` on the original inputs.
+    fn check_matmul_transposed(di: u32, dj: u32, dk: u32, a_bs: u32, b_bs: u32) {
+        let bs = a_bs.max(b_bs);
+        let seq = |n: u32, m: u32| (0..n).map(|x| (x % m) as f32 - 2.).collect::<Vec<_>>();
+        let a_data = seq(di * dj * a_bs, 5);
+        let b_data = seq(dj * dk * b_bs, 7);
+        let gy_data = seq(di * dk * bs, 3);
+        let dev = get_device();
+        let a = dev.new_tensor_by_slice(shape![di, dj; a_bs], &a_data);
+        let b = dev.new_tensor_by_slice(shape![dj, dk; b_bs], &b_data);
+        let gy = dev.new_tensor_by_slice(shape![di, dk; bs], &gy_data);
+        let mut y = dev.new_tensor(shape![di, dk; bs]);
+        y.alloc();
+        dev.call_fw_impl("matmul_fw_impl", &[&a, &b], &[], &[], &mut [&mut y]);
+        let mut ga = dev.new_tensor_by_constant(shape![di, dj; a_bs], 1.);
+        let mut gb = dev.new_tensor_by_constant(shape![dj, dk; b_bs], 1.);
+        dev.call_bw_impl(
+            "matmul_bw_a_impl",
+            &[&a, &b],
+            &[&y],
+            &[&gy],
+            &[],
+            &[],
+            &mut ga,
+        );
+        dev.call_bw_impl(
+            "matmul_bw_b_impl",
+            &[&a, &b],
+            &[&y],
+            &[&gy],
+            &[],
+            &[],
+            &mut gb,
+        );
+        for &(name, trans_a, trans_b) in &[
+            ("matmul_tn", true, false),
+            ("matmul_nt", false, true),
+            ("matmul_tt", true, true),
+        ] {
+            let (a_shape, a_data, ga_data) = if trans_a {
+                (
+                    shape![dj, di; a_bs],
+                    transpose(&a_data, di, dj),
+                    transpose(&ga.to_vec(), di, dj),
+                )
+            } else {
+                (shape![di, dj; a_bs], a_data.clone(), ga.to_vec())
+            };
+            let (b_shape, b_data, gb_data) = if trans_b {
+                (
+                    shape![dk, dj; b_bs],
+                    transpose(&b_data, dj, dk),
+                    transpose(&gb.to_vec(), dj, dk),
+                )
+            } else {
+                (shape![dj, dk; b_bs], b_data.clone(), gb.to_vec())
+            };
+            let a = dev.new_tensor_by_slice(a_shape.clone(), &a_data);
+            let b = dev.new_tensor_by_slice(b_shape.clone(), &b_data);
+            let mut y2 = dev.new_tensor(shape![di, dk; bs]);
+            y2.alloc();
+            dev.call_fw_impl(
+                &format!("{}_fw_impl", name),
+                &[&a, &b],
+                &[],
+                &[],
+                &mut [&mut y2],
+            );
+            let mut ga = dev.new_tensor_by_constant(a_shape, 1.);
+            let mut gb = dev.new_tensor_by_constant(b_shape, 1.);
+            dev.call_bw_impl(
+                &format!("{}_bw_a_impl", name),
+                &[&a, &b],
+                &[&y2],
+                &[&gy],
+                &[],
+                &[],
+                &mut ga,
+            );
+            dev.call_bw_impl(
+                &format!("{}_bw_b_impl", name),
+                &[&a, &b],
+                &[&y2],
+                &[&gy],
+                &[],
+                &[],
+                &mut gb,
+            );
+            assert_vector_ulps_eq!(y.to_vec(), y2.to_vec());
+            assert_vector_ulps_eq!(ga_data, ga.to_vec());
+            assert_vector_ulps_eq!(gb_data, gb.to_vec());
+        }
+    }
+
+    #[test]
+    fn check_matmul_transposed_11() {
+        check_matmul_transposed(3, 4, 5, 1, 1);
+    }
+
+    #[test]
+    fn check_matmul_transposed_1n() {
+        check_matmul_transposed(3, 4, 5, 1, 3);
+    }
+
+    #[test]
+    fn check_matmul_transposed_n1() {
+        check_matmul_transposed(3, 4, 5, 3, 1);
+    }
+
+    #[test]
+    fn check_matmul_transposed_nn() {
+        check_matmul_transposed(3, 4, 5, 3, 3);
+    }
+
+    #[test]
+    fn check_matmul_transposed_large() {
+        check_matmul_transposed(37, 21, 18, 2, 2);
+    }
 }
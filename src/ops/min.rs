@@ -2,7 +2,6 @@ use std::sync::Arc;
 use std::sync::Mutex;
 
 use ocl_core::ArgVal;
-use ocl_core::Event;
 use ocl_core::Kernel;
 use ocl_core::Program;
 
@@ -56,17 +55,9 @@ impl FunctionFwImpl for MinFwImpl {
                 ocl_core::set_kernel_arg(&kernel, 1, ArgVal::scalar(&s)).unwrap();
                 ocl_core::set_kernel_arg(&kernel, 2, ArgVal::scalar(&n)).unwrap();
                 ocl_core::set_kernel_arg(&kernel, 3, ArgVal::mem(buffer!(y))).unwrap();
-                ocl_core::enqueue_kernel(
-                    &self.internal.queue,
-                    &kernel,
-                    1,
-                    None,
-                    &[r as usize * k, 1, 1],
-                    Some([k, 1, 1]),
-                    None::<Event>,
-                    None::<&mut Event>,
-                )
-                .unwrap();
+                self.internal
+                    .enqueue_kernel(&kernel, 1, None, &[r as usize * k, 1, 1], Some([k, 1, 1]))
+                    .unwrap();
             }
         };
         match group_size {
@@ -142,17 +133,9 @@ impl FunctionBwImpl for MinBwImpl {
                 ocl_core::set_kernel_arg(&kernel, 3, ArgVal::scalar(&s)).unwrap();
                 ocl_core::set_kernel_arg(&kernel, 4, ArgVal::scalar(&n)).unwrap();
                 ocl_core::set_kernel_arg(&kernel, 5, ArgVal::mem(buffer!(gx))).unwrap();
-                ocl_core::enqueue_kernel(
-                    &self.internal.queue,
-                    &kernel,
-                    1,
-                    None,
-                    &[r as usize * k, 1, 1],
-                    Some([k, 1, 1]),
-                    None::<Event>,
-                    None::<&mut Event>,
-                )
-                .unwrap();
+                self.internal
+                    .enqueue_kernel(&kernel, 1, None, &[r as usize * k, 1, 1], Some([k, 1, 1]))
+                    .unwrap();
             }
         };
         match group_size {
@@ -1,7 +1,6 @@
 use std::cmp;
 
 use ocl_core::ArgVal;
-use ocl_core::Event;
 
 use prima_undine::device_impl::FunctionBwImpl;
 use prima_undine::device_impl::FunctionFwImpl;
@@ -48,11 +47,10 @@ impl FunctionFwImpl for PermuteDimsFwImpl {
         };
         if perm.len() != 0 {
             unsafe {
-                super::common::write_buffer(&self.internal.queue, &x_strides, &x_stride_buf);
-                super::common::write_buffer(&self.internal.queue, &y_strides, &y_stride_buf);
+                super::common::write_buffer(&self.internal, &x_strides, &x_stride_buf);
+                super::common::write_buffer(&self.internal, &y_strides, &y_stride_buf);
             }
         }
-        let queue = &self.internal.queue;
         let kernel = self.kernel.lock().unwrap();
         unsafe {
             ocl_core::set_kernel_arg(&kernel, 0, ArgVal::mem(buffer!(x))).unwrap();
@@ -61,17 +59,15 @@ impl FunctionFwImpl for PermuteDimsFwImpl {
             ocl_core::set_kernel_arg(&kernel, 3, ArgVal::mem(&y_stride_buf)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 4, ArgVal::scalar(&size)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 5, ArgVal::mem(buffer!(y))).unwrap();
-            ocl_core::enqueue_kernel(
-                &queue,
-                &kernel,
-                2,
-                None,
-                &[g1 * self.wgs[0], bs, 1],
-                Some([self.wgs[0], 1, 1]),
-                None::<Event>,
-                None::<&mut Event>,
-            )
-            .unwrap();
+            self.internal
+                .enqueue_kernel(
+                    &kernel,
+                    2,
+                    None,
+                    &[g1 * self.wgs[0], bs, 1],
+                    Some([self.wgs[0], 1, 1]),
+                )
+                .unwrap();
         }
     }
 }
@@ -123,11 +119,10 @@ impl FunctionBwImpl for PermuteDimsBwImpl {
         };
         if perm.len() != 0 {
             unsafe {
-                super::common::write_buffer(&self.internal.queue, &x_strides, &x_stride_buf);
-                super::common::write_buffer(&self.internal.queue, &y_strides, &y_stride_buf);
+                super::common::write_buffer(&self.internal, &x_strides, &x_stride_buf);
+                super::common::write_buffer(&self.internal, &y_strides, &y_stride_buf);
             }
         }
-        let queue = &self.internal.queue;
         let kernel = self.kernel.lock().unwrap();
         unsafe {
             ocl_core::set_kernel_arg(&kernel, 0, ArgVal::mem(buffer!(gy))).unwrap();
@@ -136,17 +131,15 @@ impl FunctionBwImpl for PermuteDimsBwImpl {
             ocl_core::set_kernel_arg(&kernel, 3, ArgVal::mem(&y_stride_buf)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 4, ArgVal::scalar(&size)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 5, ArgVal::mem(buffer!(gx))).unwrap();
-            ocl_core::enqueue_kernel(
-                &queue,
-                &kernel,
-                2,
-                None,
-                &[g1 * self.wgs[0], bs, 1],
-                Some([self.wgs[0], 1, 1]),
-                None::<Event>,
-                None::<&mut Event>,
-            )
-            .unwrap();
+            self.internal
+                .enqueue_kernel(
+                    &kernel,
+                    2,
+                    None,
+                    &[g1 * self.wgs[0], bs, 1],
+                    Some([self.wgs[0], 1, 1]),
+                )
+                .unwrap();
         }
     }
 }
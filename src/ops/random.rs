@@ -2,7 +2,6 @@ use std::sync::Arc;
 use std::sync::Mutex;
 
 use ocl_core::ArgVal;
-use ocl_core::Event;
 use ocl_core::Kernel;
 
 use rand::RngCore;
@@ -56,19 +55,18 @@ impl XORShiftRandomizer {
                 };
                 ocl_core::set_kernel_arg(&initialize_kernel, 0, ArgVal::mem(&seeds)).unwrap();
                 ocl_core::set_kernel_arg(&initialize_kernel, 1, ArgVal::mem(&rand_state)).unwrap();
-                unsafe {
-                    ocl_core::enqueue_kernel(
-                        &internal.queue,
-                        &initialize_kernel,
-                        1,
-                        None,
-                        &[size, 1, 1],
-                        Some([size, 1, 1]),
-                        None::<Event>,
-                        None::<&mut Event>,
-                    )
-                    .unwrap();
-                }
+                let key = rand_state.as_ptr() as usize;
+                internal.with_access(vec![], vec![key], || unsafe {
+                    internal
+                        .enqueue_kernel(
+                            &initialize_kernel,
+                            1,
+                            None,
+                            &[size, 1, 1],
+                            Some([size, 1, 1]),
+                        )
+                        .unwrap();
+                });
                 Self {
                     rand_state: rand_state,
                 }
@@ -76,6 +74,16 @@ impl XORShiftRandomizer {
             _ => panic!(),
         }
     }
+
+    // Every kernel reads and advances the shared state, so their calls must not overlap.
+    fn with_state<R, F: FnOnce(&ocl_core::Mem) -> R>(
+        &self,
+        internal: &crate::OpenCLInternal,
+        f: F,
+    ) -> R {
+        let key = self.rand_state.as_ptr() as usize;
+        internal.with_access(vec![key], vec![key], || f(&self.rand_state))
+    }
 }
 
 pub struct RandomBernoulliImpl {
@@ -118,23 +126,21 @@ impl FunctionFwImpl for RandomBernoulliImpl {
         let randomizer = self.randomizer.lock().unwrap();
         let kernel = self.kernel.lock().unwrap();
         let g1 = super::common::calc_num_blocks(size as usize, self.wgs[0]);
-        unsafe {
-            ocl_core::set_kernel_arg(&kernel, 0, ArgVal::mem(&randomizer.rand_state)).unwrap();
+        randomizer.with_state(&self.internal, |rand_state| unsafe {
+            ocl_core::set_kernel_arg(&kernel, 0, ArgVal::mem(rand_state)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 1, ArgVal::scalar(&p)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 2, ArgVal::scalar(&size)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 3, ArgVal::mem(&buffer!(y))).unwrap();
-            ocl_core::enqueue_kernel(
-                &self.internal.queue,
-                &kernel,
-                1,
-                None,
-                &[g1 * self.wgs[0], 1, 1],
-                Some([self.wgs[0], 1, 1]),
-                None::<Event>,
-                None::<&mut Event>,
-            )
-            .unwrap();
-        }
+            self.internal
+                .enqueue_kernel(
+                    &kernel,
+                    1,
+                    None,
+                    &[g1 * self.wgs[0], 1, 1],
+                    Some([self.wgs[0], 1, 1]),
+                )
+                .unwrap();
+        });
     }
 }
 
@@ -179,24 +185,22 @@ impl FunctionFwImpl for RandomUniformImpl {
         let randomizer = self.randomizer.lock().unwrap();
         let kernel = self.kernel.lock().unwrap();
         let g1 = super::common::calc_num_blocks(size as usize, self.wgs[0]);
-        unsafe {
-            ocl_core::set_kernel_arg(&kernel, 0, ArgVal::mem(&randomizer.rand_state)).unwrap();
+        randomizer.with_state(&self.internal, |rand_state| unsafe {
+            ocl_core::set_kernel_arg(&kernel, 0, ArgVal::mem(rand_state)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 1, ArgVal::scalar(&lower)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 2, ArgVal::scalar(&upper)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 3, ArgVal::scalar(&size)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 4, ArgVal::mem(&buffer!(y))).unwrap();
-            ocl_core::enqueue_kernel(
-                &self.internal.queue,
-                &kernel,
-                1,
-                None,
-                &[g1 * self.wgs[0], 1, 1],
-                Some([self.wgs[0], 1, 1]),
-                None::<Event>,
-                None::<&mut Event>,
-            )
-            .unwrap();
-        }
+            self.internal
+                .enqueue_kernel(
+                    &kernel,
+                    1,
+                    None,
+                    &[g1 * self.wgs[0], 1, 1],
+                    Some([self.wgs[0], 1, 1]),
+                )
+                .unwrap();
+        });
     }
 }
 
@@ -241,23 +245,21 @@ impl FunctionFwImpl for RandomNormalImpl {
         let randomizer = self.randomizer.lock().unwrap();
         let kernel = self.kernel.lock().unwrap();
         let g1 = super::common::calc_num_blocks(size as usize, self.wgs[0]);
-        unsafe {
-            ocl_core::set_kernel_arg(&kernel, 0, ArgVal::mem(&randomizer.rand_state)).unwrap();
+        randomizer.with_state(&self.internal, |rand_state| unsafe {
+            ocl_core::set_kernel_arg(&kernel, 0, ArgVal::mem(rand_state)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 1, ArgVal::scalar(&mean)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 2, ArgVal::scalar(&sd)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 3, ArgVal::scalar(&size)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 4, ArgVal::mem(&buffer!(y))).unwrap();
-            ocl_core::enqueue_kernel(
-                &self.internal.queue,
-                &kernel,
-                1,
-                None,
-                &[g1 * self.wgs[0], 1, 1],
-                Some([self.wgs[0], 1, 1]),
-                None::<Event>,
-                None::<&mut Event>,
-            )
-            .unwrap();
-        }
+            self.internal
+                .enqueue_kernel(
+                    &kernel,
+                    1,
+                    None,
+                    &[g1 * self.wgs[0], 1, 1],
+                    Some([self.wgs[0], 1, 1]),
+                )
+                .unwrap();
+        });
     }
 }
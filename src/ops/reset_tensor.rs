@@ -1,38 +1,70 @@
 use ocl_core::Event;
 use ocl_core::MapFlags;
+use ocl_core::Mem;
 use ocl_core::MemMap;
 
 use prima_undine::device_impl::FunctionFwImpl;
 use prima_undine::functions::BasicFunctions;
 use prima_undine::Tensor;
 
+use crate::tracer::Command;
+use crate::{Fence, OpenCLInternal};
+
 define_empty_impl!(ResetTensorImpl);
 impl FunctionFwImpl for ResetTensorImpl {
     fn call(&self, _xs: &[&Tensor], _u32data: &[u32], f32data: &[f32], ys: &mut [&mut Tensor]) {
         let k = f32data[0];
         let y = &mut ys[0];
         unsafe {
-            ocl_core::enqueue_fill_buffer(
-                &self.internal.queue,
-                buffer!(y),
-                k,
-                0,
-                y.shape().size() as usize,
-                None::<Event>,
-                None::<&mut Event>,
-                None,
-            )
-            .unwrap();
+            self.internal
+                .with_event(Command::FillBuffer, |wait_list, event| {
+                    ocl_core::enqueue_fill_buffer(
+                        self.internal.queue(),
+                        buffer!(y),
+                        k,
+                        0,
+                        y.shape().size() as usize,
+                        wait_list,
+                        event,
+                        None,
+                    )
+                })
+                .unwrap();
         }
     }
 }
 
+unsafe fn upload(internal: &OpenCLInternal, data: &[f32], buf: &Mem, offset: usize) {
+    if internal.zero_copy {
+        super::common::write_buffer_range(internal, data, buf, offset);
+    } else {
+        internal
+            .uploader
+            .lock()
+            .unwrap()
+            .upload(internal, data, buf, offset)
+            .unwrap();
+    }
+}
+
 define_empty_impl!(ResetTensorBySliceImpl);
 impl FunctionFwImpl for ResetTensorBySliceImpl {
     fn call(&self, _xs: &[&Tensor], _u32data: &[u32], f32data: &[f32], ys: &mut [&mut Tensor]) {
         let y = &mut ys[0];
         unsafe {
-            super::common::write_buffer(&self.internal.queue, f32data, buffer!(y));
+            upload(&self.internal, f32data, buffer!(y), 0);
+        }
+    }
+}
+
+define_empty_impl!(ResetTensorBySliceRangeImpl);
+impl FunctionFwImpl for ResetTensorBySliceRangeImpl {
+    fn call(&self, _xs: &[&Tensor], u32data: &[u32], f32data: &[f32], ys: &mut [&mut Tensor]) {
+        let offset = u32data[0] as usize;
+        let y = &mut ys[0];
+        assert!(offset + f32data.len() <= y.shape().size() as usize);
+        unsafe {
+            upload(&self.internal, f32data, buffer!(y), offset);
         }
     }
 }
@@ -46,46 +78,101 @@ impl FunctionFwImpl for ResetTensorByTensorImpl {
         let x_devid = x.device().identifier();
         unsafe {
             if x_devid == y.device().identifier() {
-                ocl_core::enqueue_copy_buffer::<f32, &ocl_core::Mem, &mut Event, Event>(
-                    &self.internal.queue,
-                    buffer!(x),
-                    buffer!(y),
-                    0,
-                    0,
-                    size,
-                    None::<Event>,
-                    None::<&mut Event>,
-                )
-                .unwrap();
-            } else {
-                if x_devid.starts_with("OpenCL,") {
-                    let mem: MemMap<f32> = ocl_core::enqueue_map_buffer(
-                        &self.internal.queue,
-                        buffer!(x),
-                        true,
-                        MapFlags::READ,
-                        0,
-                        size,
-                        None::<Event>,
-                        None::<&mut Event>,
-                    )
-                    .unwrap();
-                    super::common::write_buffer(
-                        &self.internal.queue,
-                        mem.as_slice(size),
-                        buffer!(y),
-                    );
+                copy_buffer(&self.internal, buffer!(x), buffer!(y), size);
+            } else if let Some(src) = OpenCLInternal::find(&x_devid) {
+                if src.shares_context(&self.internal) {
+                    copy_between_queues(&src, &self.internal, buffer!(x), buffer!(y), size);
                 } else {
-                    super::common::write_buffer(&self.internal.queue, &x.to_vec(), buffer!(y));
+                    let mem: MemMap<f32> = src
+                        .with_event(Command::MapBuffer, |wait_list, event| {
+                            ocl_core::enqueue_map_buffer(
+                                src.queue(),
+                                buffer!(x),
+                                true,
+                                MapFlags::READ,
+                                0,
+                                size,
+                                wait_list,
+                                event,
+                            )
+                        })
+                        .unwrap();
+                    super::common::write_buffer(&self.internal, mem.as_slice(size), buffer!(y));
+                    src.with_event(Command::UnmapBuffer, |wait_list, event| {
+                        ocl_core::enqueue_unmap_mem_object(
+                            src.queue(),
+                            buffer!(x),
+                            &mem,
+                            wait_list,
+                            event,
+                        )
+                    })
+                    .unwrap();
                 }
+            } else {
+                super::common::write_buffer(&self.internal, &x.to_vec(), buffer!(y));
             }
         }
     }
 }
 
+unsafe fn copy_buffer(internal: &OpenCLInternal, src: &Mem, dst: &Mem, size: usize) {
+    internal
+        .with_event(Command::CopyBuffer, |wait_list, event| {
+            ocl_core::enqueue_copy_buffer::<f32, _, _, _>(
+                internal.queue(),
+                src,
+                dst,
+                0,
+                0,
+                size,
+                wait_list,
+                event,
+            )
+        })
+        .unwrap();
+}
+
+// Copies a buffer of another device in the same context. The copy waits for the work
+// already enqueued on the source queue, and later work there waits for the copy.
+unsafe fn copy_between_queues(
+    src: &OpenCLInternal,
+    dst: &OpenCLInternal,
+    src_buf: &Mem,
+    dst_buf: &Mem,
+    size: usize,
+) {
+    let ready = Fence::new(src.queue()).unwrap();
+    ocl_core::enqueue_barrier_with_wait_list(
+        dst.queue(),
+        Some(ready.event()),
+        None::<&mut Event>,
+        None,
+    )
+    .unwrap();
+    let (_, copied) = dst
+        .enqueue_with_event(Command::CopyBuffer, |wait_list, event| {
+            ocl_core::enqueue_copy_buffer::<f32, _, _, _>(
+                dst.queue(),
+                src_buf,
+                dst_buf,
+                0,
+                0,
+                size,
+                wait_list,
+                event,
+            )
+        })
+        .unwrap();
+    ocl_core::flush(dst.queue()).unwrap();
+    ocl_core::enqueue_barrier_with_wait_list(src.queue(), Some(&copied), None::<&mut Event>, None)
+        .unwrap();
+}
+
 #[cfg(test)]
 mod tests {
     use crate::test_utils::get_device;
+    use crate::{DeviceSelector, OpenCL, OpenCLBuilder};
     use prima_undine::devices as D;
     use prima_undine::functions::BasicFunctions;
     use prima_undine::shape;
@@ -120,4 +207,70 @@ mod tests {
         assert_vector_ulps_eq!(x_data, y1.to_vec());
         assert_vector_ulps_eq!(x_data, y2.to_vec());
     }
+
+    #[test]
+    fn check_reset_tensor_across_devices() {
+        let x_data = (0..24).map(|i| i as f32).collect::<Vec<_>>();
+        let device_id = OpenCL::from_device(get_device())
+            .unwrap()
+            .properties()
+            .device_id;
+        let devs = OpenCLBuilder::new()
+            .selector(DeviceSelector::from_env().unwrap())
+            .build_shared(&[device_id, device_id])
+            .unwrap();
+        assert_ne!(devs[0].identifier(), devs[1].identifier());
+        let other_dev = get_device();
+        let mut x1 = devs[0].new_tensor_by_slice(shape![3, 4; 2], &x_data);
+        let x2 = other_dev.new_tensor_by_slice(shape![3, 4; 2], &x_data);
+        let mut y1 = devs[1].new_tensor(shape![3, 4; 2]);
+        let mut y2 = devs[1].new_tensor(shape![3, 4; 2]);
+        y1.alloc();
+        y2.alloc();
+        // shared context
+        devs[1].call_fw_impl(
+            "reset_tensor_by_tensor_impl",
+            &[&x1],
+            &[],
+            &[],
+            &mut [&mut y1],
+        );
+        devs[0].call_fw_impl("reset_tensor_impl", &[], &[], &[0.], &mut [&mut x1]);
+        // separate contexts
+        devs[1].call_fw_impl(
+            "reset_tensor_by_tensor_impl",
+            &[&x2],
+            &[],
+            &[],
+            &mut [&mut y2],
+        );
+        assert_vector_ulps_eq!(x_data, y1.to_vec());
+        assert_vector_ulps_eq!(x_data, y2.to_vec());
+        assert_vector_ulps_eq!(vec![0.; 24], x1.to_vec());
+    }
+
+    #[test]
+    fn check_reset_tensor_by_slice_range() {
+        let dev = get_device();
+        let mut x = dev.new_tensor_by_constant(shape![2, 2; 3], 0.);
+        dev.call_fw_impl(
+            "reset_tensor_by_slice_range_impl",
+            &[],
+            &[1],
+            &[1., 2.],
+            &mut [&mut x],
+        );
+        // batch element 2
+        dev.call_fw_impl(
+            "reset_tensor_by_slice_range_impl",
+            &[],
+            &[8],
+            &[3., 4., 5., 6.],
+            &mut [&mut x],
+        );
+        assert_vector_ulps_eq!(
+            vec![0., 1., 2., 0., 0., 0., 0., 0., 3., 4., 5., 6.],
+            x.to_vec()
+        );
+    }
 }
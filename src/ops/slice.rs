@@ -1,7 +1,6 @@
 use std::cmp;
 
 use ocl_core::ArgVal;
-use ocl_core::Event;
 
 use prima_undine::device_impl::FunctionBwImpl;
 use prima_undine::device_impl::FunctionFwImpl;
@@ -21,7 +20,6 @@ impl FunctionFwImpl for SliceFwImpl {
         let skip = base * x.shape()[dim];
         let size = y.shape().size();
         let g1 = super::common::calc_num_blocks(size as usize, self.wgs[0]);
-        let queue = &self.internal.queue;
         let kernel = self.kernel.lock().unwrap();
         unsafe {
             ocl_core::set_kernel_arg(&kernel, 0, ArgVal::mem(buffer!(x))).unwrap();
@@ -30,17 +28,15 @@ impl FunctionFwImpl for SliceFwImpl {
             ocl_core::set_kernel_arg(&kernel, 3, ArgVal::scalar(&skip)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 4, ArgVal::scalar(&size)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 5, ArgVal::mem(buffer!(y))).unwrap();
-            ocl_core::enqueue_kernel(
-                &queue,
-                &kernel,
-                1,
-                None,
-                &[g1 * self.wgs[0], 1, 1],
-                Some([self.wgs[0], 1, 1]),
-                None::<Event>,
-                None::<&mut Event>,
-            )
-            .unwrap();
+            self.internal
+                .enqueue_kernel(
+                    &kernel,
+                    1,
+                    None,
+                    &[g1 * self.wgs[0], 1, 1],
+                    Some([self.wgs[0], 1, 1]),
+                )
+                .unwrap();
         }
     }
 }
@@ -67,7 +63,6 @@ impl FunctionBwImpl for SliceBwImpl {
         let nx = repeat * gx.shape().batch();
         let ny = repeat * gy.shape().batch();
         let g1 = super::common::calc_num_blocks((wy * cmp::max(nx, ny)) as usize, self.wgs[0]);
-        let queue = &self.internal.queue;
         let kernel = self.kernel.lock().unwrap();
         unsafe {
             ocl_core::set_kernel_arg(&kernel, 0, ArgVal::mem(buffer!(gy))).unwrap();
@@ -77,17 +72,15 @@ impl FunctionBwImpl for SliceBwImpl {
             ocl_core::set_kernel_arg(&kernel, 4, ArgVal::scalar(&ny)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 5, ArgVal::mem(buffer!(gx))).unwrap();
             ocl_core::set_kernel_arg(&kernel, 6, ArgVal::scalar(&ox)).unwrap();
-            ocl_core::enqueue_kernel(
-                &queue,
-                &kernel,
-                1,
-                None,
-                &[g1 * self.wgs[0], 1, 1],
-                Some([self.wgs[0], 1, 1]),
-                None::<Event>,
-                None::<&mut Event>,
-            )
-            .unwrap();
+            self.internal
+                .enqueue_kernel(
+                    &kernel,
+                    1,
+                    None,
+                    &[g1 * self.wgs[0], 1, 1],
+                    Some([self.wgs[0], 1, 1]),
+                )
+                .unwrap();
         }
     }
 }
@@ -1,7 +1,6 @@
 use std::cmp;
 
 use ocl_core::ArgVal;
-use ocl_core::Event;
 
 use prima_undine::device_impl::FunctionFwImpl;
 use prima_undine::functions::BasicFunctions;
@@ -17,7 +16,6 @@ impl FunctionFwImpl for SubAssignImpl {
         let mby = y.shape().has_batch() as u32;
         let g1 = super::common::calc_num_blocks(size as usize, self.wgs[0]);
         let g2 = cmp::max(x.shape().batch(), y.shape().batch()) as usize;
-        let queue = &self.internal.queue;
         let kernel = self.kernel.lock().unwrap();
         unsafe {
             ocl_core::set_kernel_arg(&kernel, 0, ArgVal::mem(buffer!(x))).unwrap();
@@ -25,17 +23,15 @@ impl FunctionFwImpl for SubAssignImpl {
             ocl_core::set_kernel_arg(&kernel, 2, ArgVal::scalar(&mbx)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 3, ArgVal::scalar(&mby)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 4, ArgVal::mem(buffer!(y))).unwrap();
-            ocl_core::enqueue_kernel(
-                &queue,
-                &kernel,
-                2,
-                None,
-                &[g1 * self.wgs[0], g2, 1],
-                Some([self.wgs[0], 1, 1]),
-                None::<Event>,
-                None::<&mut Event>,
-            )
-            .unwrap();
+            self.internal
+                .enqueue_kernel(
+                    &kernel,
+                    2,
+                    None,
+                    &[g1 * self.wgs[0], g2, 1],
+                    Some([self.wgs[0], 1, 1]),
+                )
+                .unwrap();
         }
     }
 }
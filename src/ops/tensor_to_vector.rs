@@ -1,4 +1,5 @@
 use prima_undine::device_impl::FunctionFwF32Impl;
+use prima_undine::functions::BasicFunctions;
 use prima_undine::Tensor;
 
 define_empty_impl!(TensorToVectorImpl);
@@ -6,7 +7,69 @@ impl FunctionFwF32Impl for TensorToVectorImpl {
     fn call(&self, xs: &[&Tensor], _u32data: &[u32], _f32data: &[f32], ys: &mut [f32]) {
         let x = &xs[0];
         unsafe {
-            super::common::read_buffer(&self.internal.queue, buffer!(x), ys);
+            super::common::read_buffer(&self.internal, buffer!(x), ys);
         }
     }
 }
+
+define_empty_impl!(TensorToVectorRangeImpl);
+impl FunctionFwF32Impl for TensorToVectorRangeImpl {
+    fn call(&self, xs: &[&Tensor], u32data: &[u32], _f32data: &[f32], ys: &mut [f32]) {
+        let offset = u32data[0] as usize;
+        let x = &xs[0];
+        assert!(offset + ys.len() <= x.shape().size() as usize);
+        unsafe {
+            super::common::read_buffer_range(&self.internal, buffer!(x), offset, ys);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::get_device;
+    use crate::OpenCL;
+    use prima_undine::functions::BasicFunctions;
+    use prima_undine::shape;
+
+    #[test]
+    fn check_tensor_to_vector_range() {
+        let x_data = vec![0., 1., 2., 0., 0., 0., 0., 0., 3., 4., 5., 6.];
+        let dev = get_device();
+        let x = dev.new_tensor_by_slice(shape![2, 2; 3], &x_data);
+        let mut y = vec![0.; 4];
+        dev.call_fw_f32_impl("tensor_to_vector_range_impl", &[&x], &[2], &[], &mut y);
+        assert_vector_ulps_eq!(vec![2., 0., 0., 0.], y);
+        let mut y = vec![0.; 4];
+        dev.call_fw_f32_impl("tensor_to_vector_range_impl", &[&x], &[8], &[], &mut y);
+        assert_vector_ulps_eq!(vec![3., 4., 5., 6.], y);
+    }
+
+    #[test]
+    #[should_panic]
+    fn check_tensor_to_vector_range_out_of_bounds() {
+        let dev = get_device();
+        let x = dev.new_tensor_by_constant(shape![2, 2], 0.);
+        let mut y = vec![0.; 2];
+        dev.call_fw_f32_impl("tensor_to_vector_range_impl", &[&x], &[3], &[], &mut y);
+    }
+
+    #[test]
+    fn check_batch_to_vector() {
+        let x_data = vec![0., 1., 2., 0., 0., 0., 0., 0., 3., 4., 5., 6.];
+        let dev = get_device();
+        let cl = OpenCL::from_device(dev).unwrap();
+        let x = dev.new_tensor_by_slice(shape![2, 2; 3], &x_data);
+        assert_eq!((8, 4), OpenCL::batch_range(&x.shape(), 2));
+        assert_vector_ulps_eq!(vec![0., 1., 2., 0.], cl.batch_to_vector(&x, 0));
+        assert_vector_ulps_eq!(vec![3., 4., 5., 6.], cl.batch_to_vector(&x, 2));
+    }
+
+    #[test]
+    #[should_panic]
+    fn check_batch_to_vector_out_of_bounds() {
+        let dev = get_device();
+        let cl = OpenCL::from_device(dev).unwrap();
+        let x = dev.new_tensor_by_constant(shape![2, 2; 3], 0.);
+        cl.batch_to_vector(&x, 3);
+    }
+}
@@ -1,5 +1,4 @@
 use ocl_core::ArgVal;
-use ocl_core::Event;
 
 use prima_undine::device_impl::FunctionBwImpl;
 use prima_undine::device_impl::FunctionFwImpl;
@@ -16,24 +15,21 @@ impl FunctionFwImpl for TriangularUFwImpl {
         let bs = x.shape().batch() as usize;
         let g1 = super::common::calc_num_blocks(size as usize, self.wgs[0]);
         let g2 = super::common::calc_num_blocks(size as usize, self.wgs[1]);
-        let queue = &self.internal.queue;
         let kernel = self.kernel.lock().unwrap();
         unsafe {
             ocl_core::set_kernel_arg(&kernel, 0, ArgVal::mem(buffer!(x))).unwrap();
             ocl_core::set_kernel_arg(&kernel, 1, ArgVal::scalar(&k)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 2, ArgVal::scalar(&size)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 3, ArgVal::mem(buffer!(y))).unwrap();
-            ocl_core::enqueue_kernel(
-                &queue,
-                &kernel,
-                3,
-                None,
-                &[g1 * self.wgs[0], g2 * self.wgs[1], bs],
-                Some([self.wgs[0], self.wgs[1], 1]),
-                None::<Event>,
-                None::<&mut Event>,
-            )
-            .unwrap();
+            self.internal
+                .enqueue_kernel(
+                    &kernel,
+                    3,
+                    None,
+                    &[g1 * self.wgs[0], g2 * self.wgs[1], bs],
+                    Some([self.wgs[0], self.wgs[1], 1]),
+                )
+                .unwrap();
         }
     }
 }
@@ -55,24 +51,21 @@ impl FunctionBwImpl for TriangularUBwImpl {
         let bs = gx.shape().batch() as usize;
         let g1 = super::common::calc_num_blocks(size as usize, self.wgs[0]);
         let g2 = super::common::calc_num_blocks(size as usize, self.wgs[1]);
-        let queue = &self.internal.queue;
         let kernel = self.kernel.lock().unwrap();
         unsafe {
             ocl_core::set_kernel_arg(&kernel, 0, ArgVal::mem(buffer!(gy))).unwrap();
             ocl_core::set_kernel_arg(&kernel, 1, ArgVal::scalar(&k)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 2, ArgVal::scalar(&size)).unwrap();
             ocl_core::set_kernel_arg(&kernel, 3, ArgVal::mem(buffer!(gx))).unwrap();
-            ocl_core::enqueue_kernel(
-                &queue,
-                &kernel,
-                3,
-                None,
-                &[g1 * self.wgs[0], g2 * self.wgs[1], bs],
-                Some([self.wgs[0], self.wgs[1], 1]),
-                None::<Event>,
-                None::<&mut Event>,
-            )
-            .unwrap();
+            self.internal
+                .enqueue_kernel(
+                    &kernel,
+                    3,
+                    None,
+                    &[g1 * self.wgs[0], g2 * self.wgs[1], bs],
+                    Some([self.wgs[0], self.wgs[1], 1]),
+                )
+                .unwrap();
         }
     }
 }
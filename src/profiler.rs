@@ -0,0 +1,353 @@
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::mem;
+use std::sync::Mutex;
+
+use ocl_core::{Event, ProfilingInfo, ProfilingInfoResult};
+
+use crate::OpenCLError;
+
+// Finished events are collected once this many commands are pending.
+const MAX_PENDING_EVENTS: usize = 4096;
+
+thread_local! {
+    // The impl running on this thread, and whether it has enqueued a compute command yet.
+    static CURRENT_IMPL: Cell<(&'static str, bool)> = Cell::new(("", false));
+}
+
+pub fn with_current_impl<R, F: FnOnce() -> R>(name: &'static str, f: F) -> R {
+    let prev = CURRENT_IMPL.with(|current| current.replace((name, false)));
+    let ret = f();
+    CURRENT_IMPL.with(|current| current.set(prev));
+    ret
+}
+
+pub fn current_impl() -> &'static str {
+    CURRENT_IMPL.with(|current| current.get().0)
+}
+
+// The current impl, and whether this is the first compute command of its call.
+fn start_command() -> (&'static str, bool) {
+    CURRENT_IMPL.with(|current| {
+        let (name, started) = current.get();
+        current.set((name, true));
+        (name, !started)
+    })
+}
+
+pub fn event_time(event: &Event, info: ProfilingInfo) -> Result<u64, ocl_core::Error> {
+    match ocl_core::get_event_profiling_info(event, info)? {
+        ProfilingInfoResult::Queued(t)
+        | ProfilingInfoResult::Submit(t)
+        | ProfilingInfoResult::Start(t)
+        | ProfilingInfoResult::End(t) => Ok(t),
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct EventTimes {
+    pub queued_ns: u64,
+    pub submit_ns: u64,
+    pub start_ns: u64,
+    pub end_ns: u64,
+}
+
+impl EventTimes {
+    fn new(event: &Event) -> Result<EventTimes, ocl_core::Error> {
+        Ok(EventTimes {
+            queued_ns: event_time(event, ProfilingInfo::Queued)?,
+            submit_ns: event_time(event, ProfilingInfo::Submit)?,
+            start_ns: event_time(event, ProfilingInfo::Start)?,
+            end_ns: event_time(event, ProfilingInfo::End)?,
+        })
+    }
+}
+
+// Profiled commands shared by the profiler and the tracer. Recording never blocks or fails:
+// finished events are collected along the way, and the first error is kept for `resolve`.
+pub struct EventCollector<T> {
+    pending: Vec<(T, Event)>,
+    resolved: Vec<(T, EventTimes)>,
+    error: Option<ocl_core::Error>,
+}
+
+impl<T> EventCollector<T> {
+    pub fn new() -> EventCollector<T> {
+        EventCollector {
+            pending: vec![],
+            resolved: vec![],
+            error: None,
+        }
+    }
+
+    pub fn record(&mut self, tag: T, event: Event) {
+        self.pending.push((tag, event));
+        if self.pending.len() >= MAX_PENDING_EVENTS {
+            self.collect(false);
+        }
+    }
+
+    fn collect(&mut self, wait: bool) {
+        for (tag, event) in mem::replace(&mut self.pending, vec![]) {
+            let complete = if wait {
+                ocl_core::wait_for_event(&event).map(|_| true)
+            } else {
+                ocl_core::event_is_complete(&event)
+            };
+            match complete.and_then(|complete| {
+                if complete {
+                    EventTimes::new(&event).map(Some)
+                } else {
+                    Ok(None)
+                }
+            }) {
+                Ok(Some(times)) => self.resolved.push((tag, times)),
+                Ok(None) => self.pending.push((tag, event)),
+                Err(e) => {
+                    self.error.get_or_insert(e);
+                }
+            }
+        }
+    }
+
+    // Events collected so far, without waiting for the pending ones.
+    pub fn take_resolved(&mut self) -> Vec<(T, EventTimes)> {
+        mem::replace(&mut self.resolved, vec![])
+    }
+
+    // Waits for every pending event and returns all of them, or the first error.
+    pub fn resolve(&mut self) -> Result<Vec<(T, EventTimes)>, ocl_core::Error> {
+        self.collect(true);
+        match self.error.take() {
+            Some(e) => {
+                self.resolved.clear();
+                Err(e)
+            }
+            None => Ok(self.take_resolved()),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.pending.clear();
+        self.resolved.clear();
+        self.error = None;
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ProfileEntry {
+    pub name: String,
+    pub calls: u64,
+    pub total_ns: u64,
+}
+
+impl ProfileEntry {
+    pub fn mean_ns(&self) -> f64 {
+        if self.calls == 0 {
+            0.
+        } else {
+            self.total_ns as f64 / self.calls as f64
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ProfileReport {
+    pub entries: Vec<ProfileEntry>,
+}
+
+impl ProfileReport {
+    pub fn total_ns(&self) -> u64 {
+        self.entries.iter().map(|e| e.total_ns).sum()
+    }
+
+    pub fn to_json(&self) -> String {
+        let entries = self
+            .entries
+            .iter()
+            .map(|e| {
+                format!(
+                    "{{\"name\":{:?},\"calls\":{},\"total_ns\":{},\"mean_ns\":{}}}",
+                    e.name,
+                    e.calls,
+                    e.total_ns,
+                    e.mean_ns()
+                )
+            })
+            .collect::<Vec<_>>();
+        format!(
+            "{{\"total_ns\":{},\"entries\":[{}]}}",
+            self.total_ns(),
+            entries.join(",")
+        )
+    }
+}
+
+impl fmt::Display for ProfileReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<32} {:>10} {:>14} {:>14}",
+            "impl", "calls", "total (us)", "mean (us)"
+        )?;
+        for e in &self.entries {
+            writeln!(
+                f,
+                "{:<32} {:>10} {:>14.3} {:>14.3}",
+                e.name,
+                e.calls,
+                e.total_ns as f64 / 1000.,
+                e.mean_ns() / 1000.
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Calls are counted when they enqueue their first command; the time of every command they
+// enqueue is added to the same entry once it is resolved.
+struct ProfilerState {
+    events: EventCollector<&'static str>,
+    totals: BTreeMap<&'static str, (u64, u64)>,
+}
+
+impl ProfilerState {
+    fn add(&mut self, resolved: Vec<(&'static str, EventTimes)>) {
+        for (name, times) in resolved {
+            let total = self.totals.entry(name).or_insert((0, 0));
+            total.1 += times.end_ns.saturating_sub(times.start_ns);
+        }
+    }
+}
+
+pub struct Profiler {
+    state: Mutex<ProfilerState>,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            state: Mutex::new(ProfilerState {
+                events: EventCollector::new(),
+                totals: BTreeMap::new(),
+            }),
+        }
+    }
+
+    pub fn record(&self, event: Event) {
+        let (name, first) = start_command();
+        let mut state = self.state.lock().unwrap();
+        if first {
+            state.totals.entry(name).or_insert((0, 0)).0 += 1;
+        }
+        state.events.record(name, event);
+        let resolved = state.events.take_resolved();
+        state.add(resolved);
+    }
+
+    pub fn report(&self) -> Result<ProfileReport, OpenCLError> {
+        let mut state = self.state.lock().unwrap();
+        let resolved = state.events.resolve().map_err(OpenCLError::Profiling)?;
+        state.add(resolved);
+        Ok(ProfileReport {
+            entries: state
+                .totals
+                .iter()
+                .map(|(name, (calls, total_ns))| ProfileEntry {
+                    name: name.to_string(),
+                    calls: *calls,
+                    total_ns: *total_ns,
+                })
+                .collect(),
+        })
+    }
+
+    pub fn reset(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.events.clear();
+        state.totals.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ProfileEntry, ProfileReport};
+    use crate::{DeviceSelector, OpenCL, OpenCLBuilder};
+    use prima_undine::functions::BasicFunctions;
+    use prima_undine::shape;
+
+    #[test]
+    fn check_report_format() {
+        let report = ProfileReport {
+            entries: vec![
+                ProfileEntry {
+                    name: "add_fw_impl".to_string(),
+                    calls: 2,
+                    total_ns: 3000,
+                },
+                ProfileEntry {
+                    name: "sum_fw_impl".to_string(),
+                    calls: 1,
+                    total_ns: 500,
+                },
+            ],
+        };
+        assert_eq!(3500, report.total_ns());
+        assert_eq!(
+            "{\"total_ns\":3500,\"entries\":[\
+             {\"name\":\"add_fw_impl\",\"calls\":2,\"total_ns\":3000,\"mean_ns\":1500},\
+             {\"name\":\"sum_fw_impl\",\"calls\":1,\"total_ns\":500,\"mean_ns\":500}]}",
+            report.to_json()
+        );
+        let table = report.to_string();
+        assert_eq!(3, table.lines().count());
+        assert!(table.lines().nth(1).unwrap().starts_with("add_fw_impl"));
+    }
+
+    #[test]
+    fn check_profiling() {
+        let dev = OpenCLBuilder::new()
+            .selector(DeviceSelector::from_env().unwrap())
+            .profiling(true)
+            .build()
+            .unwrap();
+        let cl = OpenCL::from_device(&dev).unwrap();
+        let a = dev.new_tensor_by_slice(shape![2, 2], &[1., 2., 3., 4.]);
+        let b = dev.new_tensor_by_slice(shape![2, 2], &[1., 0., 0., 1.]);
+        let mut y = dev.new_tensor(shape![2, 2]);
+        y.alloc();
+        cl.reset_profile();
+        dev.call_fw_impl("add_fw_impl", &[&a, &b], &[], &[], &mut [&mut y]);
+        dev.call_fw_impl("add_fw_impl", &[&a, &b], &[], &[], &mut [&mut y]);
+        dev.call_fw_impl("matmul_fw_impl", &[&a, &b], &[], &[], &mut [&mut y]);
+        // One GEMM per batch element of `b`, but a single call of the impl.
+        let b3 = dev.new_tensor_by_constant(shape![2, 2; 3], 1.);
+        let gy3 = dev.new_tensor_by_constant(shape![2, 2; 3], 1.);
+        let mut ga = dev.new_tensor_by_constant(shape![2, 2], 0.);
+        dev.call_bw_impl(
+            "matmul_nt_bw_a_impl",
+            &[&a, &b3],
+            &[&gy3],
+            &[&gy3],
+            &[],
+            &[],
+            &mut ga,
+        );
+        let report = cl.profile_report().unwrap().unwrap();
+        let calls = |name| {
+            report
+                .entries
+                .iter()
+                .find(|e| e.name == name)
+                .map(|e| e.calls)
+        };
+        assert_eq!(Some(2), calls("add_fw_impl"));
+        assert_eq!(Some(1), calls("matmul_fw_impl"));
+        assert_eq!(Some(1), calls("matmul_nt_bw_a_impl"));
+        assert_eq!(None, calls("sum_fw_impl"));
+
+        let plain = OpenCL::from_device(crate::test_utils::get_device()).unwrap();
+        assert!(plain.profile_report().is_none());
+    }
+}
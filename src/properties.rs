@@ -0,0 +1,165 @@
+use ocl_core::{
+    CommandQueueProperties, DeviceId, DeviceInfo, DeviceInfoResult, DeviceType, PlatformId,
+};
+use ocl_core::{PlatformInfo, PlatformInfoResult};
+
+use crate::OpenCLError;
+
+macro_rules! platform_info {
+    ( $platform:expr, $info:ident ) => {
+        match ocl_core::get_platform_info($platform, PlatformInfo::$info)
+            .map_err(OpenCLError::PlatformQuery)?
+        {
+            PlatformInfoResult::$info(v) => v,
+            _ => panic!(),
+        }
+    };
+}
+
+macro_rules! device_info {
+    ( $device:expr, $info:ident ) => {
+        match ocl_core::get_device_info($device, DeviceInfo::$info)
+            .map_err(OpenCLError::PlatformQuery)?
+        {
+            DeviceInfoResult::$info(v) => v,
+            _ => panic!(),
+        }
+    };
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceKind {
+    Cpu,
+    Gpu,
+    Accelerator,
+    Other,
+}
+
+#[derive(Clone, Debug)]
+pub struct DeviceProperties {
+    pub platform_id: usize,
+    pub device_id: usize,
+    pub name: String,
+    pub vendor: String,
+    pub driver_version: String,
+    pub kind: DeviceKind,
+    pub global_mem_size: u64,
+    pub local_mem_size: u64,
+    pub max_work_group_size: usize,
+    pub max_compute_units: u32,
+    pub host_unified_memory: bool,
+    pub queue_properties: CommandQueueProperties,
+    pub extensions: Vec<String>,
+}
+
+impl DeviceProperties {
+    pub(crate) fn query(
+        platform_id: usize,
+        device_id: usize,
+        device: &DeviceId,
+    ) -> Result<DeviceProperties, OpenCLError> {
+        let device_type: DeviceType = device_info!(device, Type);
+        let kind = if device_type.contains(DeviceType::GPU) {
+            DeviceKind::Gpu
+        } else if device_type.contains(DeviceType::CPU) {
+            DeviceKind::Cpu
+        } else if device_type.contains(DeviceType::ACCELERATOR) {
+            DeviceKind::Accelerator
+        } else {
+            DeviceKind::Other
+        };
+        let extensions: String = device_info!(device, Extensions);
+        Ok(DeviceProperties {
+            platform_id: platform_id,
+            device_id: device_id,
+            name: device_info!(device, Name),
+            vendor: device_info!(device, Vendor),
+            driver_version: device_info!(device, DriverVersion),
+            kind: kind,
+            global_mem_size: device_info!(device, GlobalMemSize),
+            local_mem_size: device_info!(device, LocalMemSize),
+            max_work_group_size: device_info!(device, MaxWorkGroupSize),
+            max_compute_units: device_info!(device, MaxComputeUnits),
+            host_unified_memory: device_info!(device, HostUnifiedMemory),
+            queue_properties: device_info!(device, QueueProperties),
+            extensions: extensions.split_whitespace().map(String::from).collect(),
+        })
+    }
+
+    pub fn has_extension(&self, name: &str) -> bool {
+        self.extensions.iter().any(|ext| ext == name)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PlatformProperties {
+    pub platform_id: usize,
+    pub name: String,
+    pub vendor: String,
+    pub version: String,
+    pub devices: Vec<DeviceProperties>,
+}
+
+impl PlatformProperties {
+    fn query(platform_id: usize, platform: &PlatformId) -> Result<PlatformProperties, OpenCLError> {
+        let devices =
+            ocl_core::get_device_ids(platform, None, None).map_err(OpenCLError::PlatformQuery)?;
+        Ok(PlatformProperties {
+            platform_id: platform_id,
+            name: platform_info!(platform, Name),
+            vendor: platform_info!(platform, Vendor),
+            version: platform_info!(platform, Version),
+            devices: devices
+                .iter()
+                .enumerate()
+                .map(|(device_id, device)| DeviceProperties::query(platform_id, device_id, device))
+                .collect::<Result<Vec<_>, _>>()?,
+        })
+    }
+}
+
+pub(crate) fn platforms() -> Result<Vec<PlatformProperties>, OpenCLError> {
+    let platforms = ocl_core::get_platform_ids().map_err(OpenCLError::PlatformQuery)?;
+    platforms
+        .iter()
+        .enumerate()
+        .map(|(platform_id, platform)| PlatformProperties::query(platform_id, platform))
+        .collect()
+}
+
+pub(crate) fn devices() -> Result<Vec<DeviceProperties>, OpenCLError> {
+    Ok(platforms()?
+        .into_iter()
+        .flat_map(|platform| platform.devices)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_platforms() {
+        let platforms = platforms().unwrap();
+        assert!(!platforms.is_empty());
+        for (i, platform) in platforms.iter().enumerate() {
+            assert_eq!(i, platform.platform_id);
+            for (j, device) in platform.devices.iter().enumerate() {
+                assert_eq!(i, device.platform_id);
+                assert_eq!(j, device.device_id);
+                assert!(device.global_mem_size > 0);
+                assert!(device.max_work_group_size > 0);
+                assert!(device.max_compute_units > 0);
+            }
+        }
+    }
+
+    #[test]
+    fn check_devices() {
+        let devices = devices().unwrap();
+        assert!(!devices.is_empty());
+        assert!(devices
+            .iter()
+            .any(|d| d.platform_id == 0 && d.device_id == 0));
+    }
+}
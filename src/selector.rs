@@ -0,0 +1,137 @@
+use std::env;
+
+use crate::{DeviceKind, DeviceProperties, OpenCLError};
+
+pub const PLATFORM_ENV: &str = "PRIMA_OPENCL_PLATFORM";
+pub const DEVICE_ENV: &str = "PRIMA_OPENCL_DEVICE";
+
+#[derive(Clone, Debug)]
+pub enum DeviceSelector {
+    Index(usize, usize),
+    FirstOfKind(DeviceKind),
+    NameContains(String),
+    VendorContains(String),
+    LargestGlobalMemory,
+}
+
+impl DeviceSelector {
+    // Both variables default to 0. A non-numeric `PRIMA_OPENCL_DEVICE` is
+    // matched against device names when no platform is given.
+    pub fn from_env() -> Result<DeviceSelector, OpenCLError> {
+        let platform_id = match env::var(PLATFORM_ENV) {
+            Ok(value) => Some(value.trim().parse::<usize>().map_err(|_| {
+                OpenCLError::InvalidEnvironment {
+                    name: PLATFORM_ENV,
+                    value: value.clone(),
+                }
+            })?),
+            Err(_) => None,
+        };
+        match env::var(DEVICE_ENV) {
+            Ok(value) => match value.trim().parse::<usize>() {
+                Ok(device_id) => Ok(DeviceSelector::Index(platform_id.unwrap_or(0), device_id)),
+                Err(_) => match platform_id {
+                    Some(_) => Err(OpenCLError::InvalidEnvironment {
+                        name: DEVICE_ENV,
+                        value: value,
+                    }),
+                    None => Ok(DeviceSelector::NameContains(value)),
+                },
+            },
+            Err(_) => Ok(DeviceSelector::Index(platform_id.unwrap_or(0), 0)),
+        }
+    }
+
+    pub fn select<'a>(&self, devices: &'a [DeviceProperties]) -> Option<&'a DeviceProperties> {
+        match self {
+            DeviceSelector::Index(platform_id, device_id) => devices
+                .iter()
+                .find(|d| d.platform_id == *platform_id && d.device_id == *device_id),
+            DeviceSelector::FirstOfKind(kind) => devices.iter().find(|d| d.kind == *kind),
+            DeviceSelector::NameContains(name) => {
+                let name = name.to_lowercase();
+                devices
+                    .iter()
+                    .find(|d| d.name.to_lowercase().contains(&name))
+            }
+            DeviceSelector::VendorContains(vendor) => {
+                let vendor = vendor.to_lowercase();
+                devices
+                    .iter()
+                    .find(|d| d.vendor.to_lowercase().contains(&vendor))
+            }
+            DeviceSelector::LargestGlobalMemory => {
+                // Keeps the first device among those with the same memory size.
+                devices.iter().fold(None, |best, d| match best {
+                    Some(b) if b.global_mem_size >= d.global_mem_size => Some(b),
+                    _ => Some(d),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DeviceSelector;
+    use crate::{DeviceKind, DeviceProperties};
+    use ocl_core::CommandQueueProperties;
+
+    fn device(
+        platform_id: usize,
+        device_id: usize,
+        name: &str,
+        kind: DeviceKind,
+        global_mem_size: u64,
+    ) -> DeviceProperties {
+        DeviceProperties {
+            platform_id: platform_id,
+            device_id: device_id,
+            name: name.to_string(),
+            vendor: "Vendor ".to_string() + name,
+            driver_version: "1.0".to_string(),
+            kind: kind,
+            global_mem_size: global_mem_size,
+            local_mem_size: 32768,
+            max_work_group_size: 256,
+            max_compute_units: 8,
+            host_unified_memory: false,
+            queue_properties: CommandQueueProperties::empty(),
+            extensions: vec![],
+        }
+    }
+
+    #[test]
+    fn check_select() {
+        let devices = vec![
+            device(0, 0, "pthread-Intel CPU", DeviceKind::Cpu, 1 << 30),
+            device(1, 0, "GeForce GTX 1080", DeviceKind::Gpu, 8 << 30),
+            device(1, 1, "GeForce GTX 1060", DeviceKind::Gpu, 6 << 30),
+            device(2, 0, "Radeon VII", DeviceKind::Gpu, 16 << 30),
+        ];
+        let select = |selector: DeviceSelector| {
+            selector
+                .select(&devices)
+                .map(|d| (d.platform_id, d.device_id))
+        };
+        assert_eq!(Some((1, 1)), select(DeviceSelector::Index(1, 1)));
+        assert_eq!(None, select(DeviceSelector::Index(0, 1)));
+        assert_eq!(
+            Some((1, 0)),
+            select(DeviceSelector::FirstOfKind(DeviceKind::Gpu))
+        );
+        assert_eq!(
+            None,
+            select(DeviceSelector::FirstOfKind(DeviceKind::Accelerator))
+        );
+        assert_eq!(
+            Some((1, 1)),
+            select(DeviceSelector::NameContains("gtx 1060".to_string()))
+        );
+        assert_eq!(
+            Some((2, 0)),
+            select(DeviceSelector::VendorContains("Radeon".to_string()))
+        );
+        assert_eq!(Some((2, 0)), select(DeviceSelector::LargestGlobalMemory));
+    }
+}
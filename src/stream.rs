@@ -0,0 +1,73 @@
+use std::cell::Cell;
+
+thread_local! {
+    // (owner, stream): the stream selected for the OpenCLInternal at address `owner`.
+    static CURRENT_STREAM: Cell<(usize, usize)> = Cell::new((0, 0));
+}
+
+pub fn with_stream<R, F: FnOnce() -> R>(owner: usize, stream: usize, f: F) -> R {
+    let prev = CURRENT_STREAM.with(|current| current.replace((owner, stream)));
+    let ret = f();
+    CURRENT_STREAM.with(|current| current.set(prev));
+    ret
+}
+
+pub fn current_stream(owner: usize) -> usize {
+    match CURRENT_STREAM.with(|current| current.get()) {
+        (o, stream) if o == owner => stream,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DeviceSelector, OpenCL, OpenCLBuilder};
+    use prima_undine::functions::BasicFunctions;
+    use prima_undine::shape;
+
+    #[test]
+    fn check_current_stream() {
+        assert_eq!(0, super::current_stream(1));
+        super::with_stream(1, 2, || {
+            assert_eq!(2, super::current_stream(1));
+            assert_eq!(0, super::current_stream(3));
+            super::with_stream(1, 1, || assert_eq!(1, super::current_stream(1)));
+            assert_eq!(2, super::current_stream(1));
+        });
+        assert_eq!(0, super::current_stream(1));
+    }
+
+    #[test]
+    fn check_streams() {
+        let dev = OpenCLBuilder::new()
+            .selector(DeviceSelector::from_env().unwrap())
+            .streams(1)
+            .build()
+            .unwrap();
+        let cl = OpenCL::from_device(&dev).unwrap();
+        assert_eq!(2, cl.num_streams());
+        let x_data = (0..1024).map(|i| i as f32).collect::<Vec<_>>();
+
+        // upload on stream 1, compute on stream 0
+        let (x, uploaded) = cl.with_stream(1, || {
+            let x = dev.new_tensor_by_slice(shape![32, 32], &x_data);
+            (x, cl.fence().unwrap())
+        });
+        cl.wait_on(&uploaded).unwrap();
+        let mut y = dev.new_tensor(shape![32, 32]);
+        y.alloc();
+        dev.call_fw_impl("neg_fw_impl", &[&x], &[], &[], &mut [&mut y]);
+        let computed = cl.fence().unwrap();
+
+        cl.with_stream(1, || {
+            cl.wait_on(&computed).unwrap();
+            assert_vector_ulps_eq!(x_data.iter().map(|x| -x).collect::<Vec<_>>(), y.to_vec());
+        });
+
+        // Buffers released on one stream are reused safely on another.
+        drop(x);
+        let z = cl.with_stream(1, || dev.new_tensor_by_constant(shape![32, 32], 2.));
+        cl.synchronize().unwrap();
+        assert_vector_ulps_eq!(vec![2.; 1024], z.to_vec());
+    }
+}
@@ -13,7 +13,7 @@ macro_rules! assert_vector_ulps_eq {
 }
 
 lazy_static! {
-    static ref DEVICE: Device<'static> = { crate::OpenCL::new(0, 0) };
+    static ref DEVICE: Device<'static> = { crate::OpenCL::from_env().unwrap() };
 }
 
 pub fn get_device() -> &'static Device<'static> {
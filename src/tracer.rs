@@ -0,0 +1,243 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+
+use ocl_core::Event;
+
+use crate::profiler::{current_impl, EventCollector, EventTimes};
+use crate::OpenCLError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    Kernel,
+    Gemm,
+    FillBuffer,
+    CopyBuffer,
+    WriteBuffer,
+    MapBuffer,
+    UnmapBuffer,
+}
+
+impl Command {
+    pub fn name(self) -> &'static str {
+        match self {
+            Command::Kernel => "kernel",
+            Command::Gemm => "gemm",
+            Command::FillBuffer => "fill_buffer",
+            Command::CopyBuffer => "copy_buffer",
+            Command::WriteBuffer => "write_buffer",
+            Command::MapBuffer => "map_buffer",
+            Command::UnmapBuffer => "unmap_buffer",
+        }
+    }
+
+    pub fn is_compute(self) -> bool {
+        match self {
+            Command::Kernel | Command::Gemm => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TraceEvent {
+    pub name: String,
+    pub command: String,
+    pub queued_ns: u64,
+    pub submit_ns: u64,
+    pub start_ns: u64,
+    pub end_ns: u64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Trace {
+    pub events: Vec<TraceEvent>,
+}
+
+impl Trace {
+    // Chrome trace event format, readable by chrome://tracing and Perfetto.
+    // Thread 0 shows the time each command waited in the queue, thread 1 its execution.
+    pub fn to_json(&self) -> String {
+        let origin = self.events.iter().map(|e| e.queued_ns).min().unwrap_or(0);
+        let us = |t: u64| t.saturating_sub(origin) as f64 / 1000.;
+        let mut entries = vec![
+            "{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":0,\"tid\":0,\"args\":{\"name\":\"queued\"}}"
+                .to_string(),
+            "{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":0,\"tid\":1,\"args\":{\"name\":\"device\"}}"
+                .to_string(),
+        ];
+        for e in &self.events {
+            let args = format!(
+                "{{\"queued\":{},\"submit\":{},\"start\":{},\"end\":{}}}",
+                e.queued_ns, e.submit_ns, e.start_ns, e.end_ns
+            );
+            if e.start_ns > e.queued_ns {
+                entries.push(format!(
+                    "{{\"name\":{:?},\"cat\":\"wait\",\"ph\":\"X\",\"pid\":0,\"tid\":0,\"ts\":{:.3},\"dur\":{:.3},\"args\":{}}}",
+                    e.name,
+                    us(e.queued_ns),
+                    us(e.start_ns) - us(e.queued_ns),
+                    args
+                ));
+            }
+            entries.push(format!(
+                "{{\"name\":{:?},\"cat\":{:?},\"ph\":\"X\",\"pid\":0,\"tid\":1,\"ts\":{:.3},\"dur\":{:.3},\"args\":{}}}",
+                e.name,
+                e.command,
+                us(e.start_ns),
+                us(e.end_ns) - us(e.start_ns),
+                args
+            ));
+        }
+        format!(
+            "{{\"traceEvents\":[{}],\"displayTimeUnit\":\"ns\"}}",
+            entries.join(",")
+        )
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_json())
+    }
+}
+
+struct TracerState {
+    pending: EventCollector<(Command, &'static str)>,
+    events: Vec<TraceEvent>,
+}
+
+impl TracerState {
+    fn add(&mut self, resolved: Vec<((Command, &'static str), EventTimes)>) {
+        for ((command, name), times) in resolved {
+            self.events.push(TraceEvent {
+                name: if name.is_empty() {
+                    command.name().to_string()
+                } else {
+                    name.to_string()
+                },
+                command: command.name().to_string(),
+                queued_ns: times.queued_ns,
+                submit_ns: times.submit_ns,
+                start_ns: times.start_ns,
+                end_ns: times.end_ns,
+            });
+        }
+    }
+}
+
+pub struct Tracer {
+    state: Mutex<TracerState>,
+}
+
+impl Tracer {
+    pub fn new() -> Tracer {
+        Tracer {
+            state: Mutex::new(TracerState {
+                pending: EventCollector::new(),
+                events: vec![],
+            }),
+        }
+    }
+
+    pub fn record(&self, command: Command, event: Event) {
+        let mut state = self.state.lock().unwrap();
+        state.pending.record((command, current_impl()), event);
+        let resolved = state.pending.take_resolved();
+        state.add(resolved);
+    }
+
+    pub fn trace(&self) -> Result<Trace, OpenCLError> {
+        let mut state = self.state.lock().unwrap();
+        let resolved = state.pending.resolve().map_err(OpenCLError::Profiling)?;
+        state.add(resolved);
+        Ok(Trace {
+            events: state.events.clone(),
+        })
+    }
+
+    pub fn reset(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.pending.clear();
+        state.events.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Trace, TraceEvent};
+    use crate::{DeviceSelector, OpenCL, OpenCLBuilder};
+    use prima_undine::functions::BasicFunctions;
+    use prima_undine::shape;
+
+    #[test]
+    fn check_trace_format() {
+        let trace = Trace {
+            events: vec![TraceEvent {
+                name: "add_fw_impl".to_string(),
+                command: "kernel".to_string(),
+                queued_ns: 1000,
+                submit_ns: 1500,
+                start_ns: 3000,
+                end_ns: 4500,
+            }],
+        };
+        let json = trace.to_json();
+        assert!(json.starts_with("{\"traceEvents\":["));
+        assert!(json.contains(
+            "{\"name\":\"add_fw_impl\",\"cat\":\"wait\",\"ph\":\"X\",\"pid\":0,\"tid\":0,\
+             \"ts\":0.000,\"dur\":2.000,"
+        ));
+        assert!(json.contains(
+            "{\"name\":\"add_fw_impl\",\"cat\":\"kernel\",\"ph\":\"X\",\"pid\":0,\"tid\":1,\
+             \"ts\":2.000,\"dur\":1.500,\
+             \"args\":{\"queued\":1000,\"submit\":1500,\"start\":3000,\"end\":4500}}"
+        ));
+        assert_eq!(
+            "{\"traceEvents\":[\
+             {\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":0,\"tid\":0,\"args\":{\"name\":\"queued\"}},\
+             {\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":0,\"tid\":1,\"args\":{\"name\":\"device\"}}],\
+             \"displayTimeUnit\":\"ns\"}",
+            Trace::default().to_json()
+        );
+    }
+
+    #[test]
+    fn check_tracing() {
+        let dev = OpenCLBuilder::new()
+            .selector(DeviceSelector::from_env().unwrap())
+            .tracing(true)
+            .build()
+            .unwrap();
+        let cl = OpenCL::from_device(&dev).unwrap();
+        cl.reset_trace();
+        let a = dev.new_tensor_by_slice(shape![2, 2], &[1., 2., 3., 4.]);
+        let b = dev.new_tensor_by_slice(shape![2, 2], &[1., 0., 0., 1.]);
+        let mut y = dev.new_tensor(shape![2, 2]);
+        y.alloc();
+        dev.call_fw_impl("add_fw_impl", &[&a, &b], &[], &[], &mut [&mut y]);
+        dev.call_fw_impl("matmul_fw_impl", &[&a, &b], &[], &[], &mut [&mut y]);
+        assert_vector_ulps_eq!(vec![1., 2., 3., 4.], y.to_vec());
+        let trace = cl.trace().unwrap().unwrap();
+        let count = |name, command| {
+            trace
+                .events
+                .iter()
+                .filter(|e| e.name == name && e.command == command)
+                .count()
+        };
+        assert_eq!(2, count("reset_tensor_by_slice_impl", "write_buffer"));
+        assert_eq!(1, count("add_fw_impl", "kernel"));
+        assert_eq!(1, count("matmul_fw_impl", "gemm"));
+        assert_eq!(1, count("tensor_to_vector_impl", "map_buffer"));
+        for e in &trace.events {
+            assert!(e.queued_ns <= e.submit_ns);
+            assert!(e.submit_ns <= e.start_ns);
+            assert!(e.start_ns <= e.end_ns);
+        }
+        cl.reset_trace();
+        assert!(cl.trace().unwrap().unwrap().events.is_empty());
+
+        let plain = OpenCL::from_device(crate::test_utils::get_device()).unwrap();
+        assert!(plain.trace().is_none());
+    }
+}
@@ -0,0 +1,378 @@
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+
+use ocl_core::types::abs::{CommandQueue, Context, Event, Mem, MemMap};
+use ocl_core::MapFlags;
+
+use prima_undine::Tensor;
+
+use crate::allocator::size_class;
+use crate::stream;
+use crate::tracer::Command;
+use crate::{OpenCLError, OpenCLInternal};
+
+const MAX_STAGING_BUFFERS_PER_CLASS: usize = 4;
+const NUM_UPLOAD_SLOTS: usize = 2;
+
+// Host-accessible (pinned) buffers reused across asynchronous transfers.
+pub struct StagingPool {
+    context: Context,
+    free: Mutex<HashMap<usize, Vec<Mem>>>,
+}
+
+impl StagingPool {
+    pub fn new(context: &Context) -> StagingPool {
+        StagingPool {
+            context: context.clone(),
+            free: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn take(&self, len: usize) -> Result<Mem, ocl_core::Error> {
+        let class = size_class(len);
+        if let Some(buffer) = self
+            .free
+            .lock()
+            .unwrap()
+            .get_mut(&class)
+            .and_then(|buffers| buffers.pop())
+        {
+            return Ok(buffer);
+        }
+        unsafe { create_pinned_buffer(&self.context, class) }
+    }
+
+    pub fn give(&self, len: usize, buffer: Mem) {
+        let mut free = self.free.lock().unwrap();
+        let buffers = free.entry(size_class(len)).or_insert_with(Vec::new);
+        if buffers.len() < MAX_STAGING_BUFFERS_PER_CLASS {
+            buffers.push(buffer);
+        }
+    }
+}
+
+unsafe fn create_pinned_buffer(context: &Context, len: usize) -> Result<Mem, ocl_core::Error> {
+    ocl_core::create_buffer(
+        context,
+        ocl_core::MEM_READ_WRITE | ocl_core::MEM_ALLOC_HOST_PTR,
+        len,
+        None::<&[f32]>,
+    )
+}
+
+pub(crate) unsafe fn tensor_buffer(tensor: &Tensor) -> &Mem {
+    (tensor.handle().load(Ordering::Acquire) as *const Mem)
+        .as_ref()
+        .unwrap()
+}
+
+// A device-to-host copy that completes in the background.
+pub struct PendingRead {
+    internal: Arc<OpenCLInternal>,
+    staging: Option<Mem>,
+    mem: Option<MemMap<f32>>,
+    event: Event,
+    len: usize,
+}
+
+impl PendingRead {
+    pub(crate) fn new(
+        internal: &Arc<OpenCLInternal>,
+        tensor: &Tensor,
+    ) -> Result<PendingRead, OpenCLError> {
+        let len = tensor.shape().size() as usize;
+        let staging = internal
+            .staging
+            .take(len)
+            .map_err(OpenCLError::Allocation)?;
+        let (mem, event) = unsafe {
+            internal
+                .with_event(Command::CopyBuffer, |wait_list, copy_event| {
+                    ocl_core::enqueue_copy_buffer::<f32, _, _, _>(
+                        internal.queue(),
+                        tensor_buffer(tensor),
+                        &staging,
+                        0,
+                        0,
+                        len,
+                        wait_list,
+                        copy_event,
+                    )
+                })
+                .map_err(OpenCLError::Queue)?;
+            internal
+                .enqueue_with_event(Command::MapBuffer, |wait_list, event| {
+                    ocl_core::enqueue_map_buffer::<f32, _, _, _>(
+                        internal.queue(),
+                        &staging,
+                        false,
+                        MapFlags::READ,
+                        0,
+                        len,
+                        wait_list,
+                        event,
+                    )
+                })
+                .map_err(OpenCLError::Queue)?
+        };
+        ocl_core::flush(internal.queue()).map_err(OpenCLError::Queue)?;
+        Ok(PendingRead {
+            internal: Arc::clone(internal),
+            staging: Some(staging),
+            mem: Some(mem),
+            event: event,
+            len: len,
+        })
+    }
+
+    pub fn is_ready(&self) -> Result<bool, OpenCLError> {
+        ocl_core::event_is_complete(&self.event).map_err(OpenCLError::Queue)
+    }
+
+    pub fn wait(mut self) -> Result<Vec<f32>, OpenCLError> {
+        ocl_core::wait_for_event(&self.event).map_err(OpenCLError::Queue)?;
+        let data = unsafe { self.mem.as_ref().unwrap().as_slice(self.len).to_vec() };
+        self.release()?;
+        Ok(data)
+    }
+
+    fn release(&mut self) -> Result<(), OpenCLError> {
+        if let (Some(staging), Some(mem)) = (self.staging.take(), self.mem.take()) {
+            let internal = &self.internal;
+            unsafe {
+                let (_, event) = internal
+                    .enqueue_with_event(Command::UnmapBuffer, |wait_list, event| {
+                        ocl_core::enqueue_unmap_mem_object(
+                            internal.queue(),
+                            &staging,
+                            &mem,
+                            wait_list,
+                            event,
+                        )
+                    })
+                    .map_err(OpenCLError::Queue)?;
+                // Out-of-order queues could run the next copy into this buffer first.
+                if internal.dependencies.is_some() {
+                    ocl_core::wait_for_event(&event).map_err(OpenCLError::Queue)?;
+                }
+            }
+            internal.staging.give(self.len, staging);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PendingRead {
+    fn drop(&mut self) {
+        let _ = self.release();
+    }
+}
+
+struct UploadSlot {
+    buffer: Mem,
+    mem: MemMap<f32>,
+    capacity: usize,
+    event: Option<Event>,
+}
+
+// Host-to-device copies through pinned buffers that stay mapped. The slots are used
+// in turn, so the host fills one while the device still reads from the other. Each
+// stream has its own slots, which are mapped, written and unmapped on its queue only.
+pub struct Uploader {
+    context: Context,
+    queues: Vec<CommandQueue>,
+    slots: Vec<Vec<Option<UploadSlot>>>,
+    next: Vec<usize>,
+}
+
+impl Uploader {
+    pub fn new<'a, I>(context: &Context, queues: I) -> Uploader
+    where
+        I: Iterator<Item = &'a CommandQueue>,
+    {
+        let queues = queues.cloned().collect::<Vec<_>>();
+        Uploader {
+            context: context.clone(),
+            slots: queues
+                .iter()
+                .map(|_| (0..NUM_UPLOAD_SLOTS).map(|_| None).collect())
+                .collect(),
+            next: vec![0; queues.len()],
+            queues: queues,
+        }
+    }
+
+    unsafe fn prepare_slot(
+        &mut self,
+        stream: usize,
+        index: usize,
+        len: usize,
+    ) -> Result<(), ocl_core::Error> {
+        let queue = &self.queues[stream];
+        let slots = &mut self.slots[stream];
+        if let Some(slot) = &slots[index] {
+            if let Some(event) = &slot.event {
+                ocl_core::wait_for_event(event)?;
+            }
+            if slot.capacity >= len {
+                return Ok(());
+            }
+        }
+        if let Some(slot) = slots[index].take() {
+            ocl_core::enqueue_unmap_mem_object(
+                queue,
+                &slot.buffer,
+                &slot.mem,
+                None::<Event>,
+                None::<&mut Event>,
+            )?;
+        }
+        let capacity = size_class(len);
+        let buffer = create_pinned_buffer(&self.context, capacity)?;
+        let mem = ocl_core::enqueue_map_buffer::<f32, _, _, _>(
+            queue,
+            &buffer,
+            true,
+            MapFlags::WRITE,
+            0,
+            capacity,
+            None::<Event>,
+            None::<&mut Event>,
+        )?;
+        slots[index] = Some(UploadSlot {
+            buffer: buffer,
+            mem: mem,
+            capacity: capacity,
+            event: None,
+        });
+        Ok(())
+    }
+
+    pub unsafe fn upload(
+        &mut self,
+        internal: &OpenCLInternal,
+        data: &[f32],
+        dst: &Mem,
+        offset: usize,
+    ) -> Result<(), ocl_core::Error> {
+        let stream = stream::current_stream(internal.owner());
+        let index = self.next[stream];
+        self.next[stream] = (index + 1) % NUM_UPLOAD_SLOTS;
+        self.prepare_slot(stream, index, data.len())?;
+        let queue = &self.queues[stream];
+        let slot = self.slots[stream][index].as_mut().unwrap();
+        slot.mem.as_slice_mut(data.len()).copy_from_slice(data);
+        let (_, event) =
+            internal.enqueue_with_event(Command::WriteBuffer, |wait_list, event| {
+                ocl_core::enqueue_write_buffer(
+                    queue,
+                    dst,
+                    false,
+                    offset,
+                    slot.mem.as_slice(data.len()),
+                    wait_list,
+                    event,
+                )
+            })?;
+        slot.event = Some(event);
+        ocl_core::flush(queue)
+    }
+}
+
+impl Drop for Uploader {
+    fn drop(&mut self) {
+        for (queue, slots) in self.queues.iter().zip(self.slots.drain(..)) {
+            for slot in slots.into_iter().filter_map(|slot| slot) {
+                unsafe {
+                    let _ = ocl_core::enqueue_unmap_mem_object(
+                        queue,
+                        &slot.buffer,
+                        &slot.mem,
+                        None::<Event>,
+                        None::<&mut Event>,
+                    );
+                }
+            }
+            let _ = ocl_core::finish(queue);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DeviceSelector, OpenCL, OpenCLBuilder};
+    use prima_undine::functions::BasicFunctions;
+    use prima_undine::shape;
+
+    #[test]
+    fn check_read_async() {
+        let dev = OpenCL::from_env().unwrap();
+        let cl = OpenCL::from_device(&dev).unwrap();
+        let x_data = (0..1000).map(|i| i as f32).collect::<Vec<_>>();
+        let mut x = dev.new_tensor_by_slice(shape![10, 10; 10], &x_data);
+        let pending = cl.read_async(&x).unwrap();
+        // Kernels enqueued after the read must not affect its result.
+        dev.call_fw_impl("reset_tensor_impl", &[], &[], &[0.], &mut [&mut x]);
+        while !pending.is_ready().unwrap() {
+            std::thread::yield_now();
+        }
+        assert_vector_ulps_eq!(x_data, pending.wait().unwrap());
+        assert_vector_ulps_eq!(vec![0.; 1000], cl.read_async(&x).unwrap().wait().unwrap());
+
+        // Unfinished reads may be dropped.
+        let y = dev.new_tensor_by_constant(shape![3], 1.);
+        drop(cl.read_async(&y).unwrap());
+        assert_vector_ulps_eq!(vec![1.; 3], cl.read_async(&y).unwrap().wait().unwrap());
+    }
+
+    #[test]
+    fn check_double_buffered_upload() {
+        let dev = OpenCL::from_env().unwrap();
+        let data = (0..5)
+            .map(|n| {
+                (0..100 * (n + 1))
+                    .map(|i| (i + n) as f32)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        // Slots are reused and grown while earlier uploads may still be in flight.
+        let xs = data
+            .iter()
+            .map(|d| dev.new_tensor_by_slice(shape![d.len() as u32], d))
+            .collect::<Vec<_>>();
+        let mut y = dev.new_tensor(shape![100]);
+        y.alloc();
+        dev.call_fw_impl("neg_fw_impl", &[&xs[0]], &[], &[], &mut [&mut y]);
+        assert_vector_ulps_eq!(data[0].iter().map(|x| -x).collect::<Vec<_>>(), y.to_vec());
+        for (x, d) in xs.iter().zip(&data) {
+            assert_vector_ulps_eq!(d, x.to_vec());
+        }
+    }
+
+    #[test]
+    fn check_upload_on_streams() {
+        let dev = OpenCLBuilder::new()
+            .selector(DeviceSelector::from_env().unwrap())
+            .streams(1)
+            .build()
+            .unwrap();
+        let cl = OpenCL::from_device(&dev).unwrap();
+        // Each stream keeps its own slots, so interleaved uploads never share one.
+        let xs = (0..6)
+            .map(|n| {
+                let data = (0..100 * (n + 1))
+                    .map(|i| (i * n) as f32)
+                    .collect::<Vec<_>>();
+                let x = cl.with_stream(n % 2, || {
+                    dev.new_tensor_by_slice(shape![data.len() as u32], &data)
+                });
+                (x, data)
+            })
+            .collect::<Vec<_>>();
+        cl.synchronize().unwrap();
+        for (x, data) in &xs {
+            assert_vector_ulps_eq!(data, x.to_vec());
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use std::ops::Deref;
+
+use ocl_core::{MapFlags, Mem, MemMap};
+
+use prima_undine::Tensor;
+
+use crate::tracer::Command;
+use crate::transfer::tensor_buffer;
+use crate::{OpenCLError, OpenCLInternal};
+
+// A read-only mapping of a tensor's buffer. On host-backed (zero-copy) buffers the
+// mapping aliases the device memory, otherwise the driver copies it to the host.
+// The tensor is borrowed for the lifetime of the view, so no operation can write to it
+// while it is mapped.
+pub struct TensorView<'a> {
+    internal: &'a OpenCLInternal,
+    buffer: &'a Mem,
+    mem: MemMap<f32>,
+    len: usize,
+    keys: Vec<usize>,
+}
+
+impl<'a> TensorView<'a> {
+    pub(crate) fn new(
+        internal: &'a OpenCLInternal,
+        tensor: &'a Tensor,
+    ) -> Result<TensorView<'a>, OpenCLError> {
+        let len = tensor.shape().size() as usize;
+        let keys = internal.mem_keys(&[tensor]);
+        unsafe {
+            let buffer = tensor_buffer(tensor);
+            let mem = internal
+                .with_access(keys.clone(), vec![], || {
+                    internal.with_event(Command::MapBuffer, |wait_list, event| {
+                        ocl_core::enqueue_map_buffer(
+                            internal.queue(),
+                            buffer,
+                            true,
+                            MapFlags::READ,
+                            0,
+                            len,
+                            wait_list,
+                            event,
+                        )
+                    })
+                })
+                .map_err(OpenCLError::Queue)?;
+            Ok(TensorView {
+                internal: internal,
+                buffer: buffer,
+                mem: mem,
+                len: len,
+                keys: keys,
+            })
+        }
+    }
+}
+
+impl<'a> Deref for TensorView<'a> {
+    type Target = [f32];
+
+    fn deref(&self) -> &[f32] {
+        unsafe { self.mem.as_slice(self.len) }
+    }
+}
+
+impl<'a> Drop for TensorView<'a> {
+    fn drop(&mut self) {
+        let internal = self.internal;
+        let result = unsafe {
+            internal.with_access(self.keys.clone(), vec![], || {
+                internal.with_event(Command::UnmapBuffer, |wait_list, event| {
+                    ocl_core::enqueue_unmap_mem_object(
+                        internal.queue(),
+                        self.buffer,
+                        &self.mem,
+                        wait_list,
+                        event,
+                    )
+                })
+            })
+        };
+        if let Err(e) = result {
+            eprintln!("prima_undine_opencl: failed to unmap a tensor view: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DeviceSelector, OpenCL, OpenCLBuilder};
+    use prima_undine::functions::BasicFunctions;
+    use prima_undine::shape;
+
+    #[test]
+    fn check_zero_copy_view() {
+        let dev = OpenCLBuilder::new()
+            .selector(DeviceSelector::from_env().unwrap())
+            .zero_copy(true)
+            .build()
+            .unwrap();
+        let cl = OpenCL::from_device(&dev).unwrap();
+        assert_eq!(cl.properties().host_unified_memory, cl.zero_copy());
+        let a = dev.new_tensor_by_slice(shape![2, 2], &[1., 2., 3., 4.]);
+        let b = dev.new_tensor_by_slice(shape![2, 2], &[4., 3., 2., 1.]);
+        let mut y = dev.new_tensor(shape![2, 2]);
+        y.alloc();
+        dev.call_fw_impl("add_fw_impl", &[&a, &b], &[], &[], &mut [&mut y]);
+        {
+            let view = cl.view(&y).unwrap();
+            assert_vector_ulps_eq!(vec![5., 5., 5., 5.], view.to_vec());
+            let view_a = cl.view(&a).unwrap();
+            assert_vector_ulps_eq!(vec![1., 2., 3., 4.], view_a.to_vec());
+        }
+        dev.call_fw_impl("add_fw_impl", &[&a, &a], &[], &[], &mut [&mut y]);
+        assert_vector_ulps_eq!(vec![2., 4., 6., 8.], y.to_vec());
+    }
+
+    #[test]
+    fn check_view_without_zero_copy() {
+        let dev = OpenCL::from_env().unwrap();
+        let cl = OpenCL::from_device(&dev).unwrap();
+        assert!(!cl.zero_copy());
+        let x = dev.new_tensor_by_slice(shape![3], &[1., 2., 3.]);
+        assert_eq!(&[1., 2., 3.], &cl.view(&x).unwrap()[..]);
+    }
+}